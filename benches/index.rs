@@ -0,0 +1,106 @@
+//! 索引的读写基准测试，需启用`bench` feature：`cargo bench --features bench`
+
+use std::{sync::Arc, thread};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tempfile::TempDir;
+use tinykv::{BenchIndex, Config, Engine, IndexType};
+
+const KEY_NUM: usize = 10_000;
+const READ_THREADS: usize = 4;
+const OPS_PER_THREAD: usize = 2_000;
+
+const INDEX_TYPES: [(&str, IndexType); 5] = [
+    ("btree", IndexType::BTree),
+    ("art", IndexType::ART),
+    ("skiplist", IndexType::SkipList),
+    ("hash", IndexType::HashSharded),
+    ("bptree", IndexType::BPlusTree),
+];
+
+fn key(i: usize) -> Vec<u8> {
+    format!("tinykv-bench-key-{:08}", i).into_bytes()
+}
+
+/// 多个读线程与一个写线程并发执行，`write`及`read`的参数为操作序号
+fn run_mixed<W, R>(write: W, read: R)
+where
+    W: Fn(usize) + Send + Sync + 'static,
+    R: Fn(usize, usize) + Send + Sync + 'static,
+{
+    let read = Arc::new(read);
+    let writer = thread::spawn(move || (0..OPS_PER_THREAD).for_each(write));
+    let readers = (0..READ_THREADS)
+        .map(|t| {
+            let read = read.clone();
+            thread::spawn(move || (0..OPS_PER_THREAD).for_each(|i| read(t, i)))
+        })
+        .collect::<Vec<_>>();
+
+    writer.join().unwrap();
+    readers.into_iter().for_each(|r| r.join().unwrap());
+}
+
+/// 直接读写索引，不经过 Engine 及`Storage`的锁
+fn bench_index(c: &mut Criterion) {
+    let mut group = c.benchmark_group("index_mixed_read_write");
+    for (name, index_type) in INDEX_TYPES {
+        let dir = TempDir::new().unwrap();
+        let index = Arc::new(BenchIndex::new(index_type, dir.path()).unwrap());
+        for i in 0..KEY_NUM {
+            index.put(key(i), i as u64).unwrap();
+        }
+
+        group.bench_with_input(BenchmarkId::from_parameter(name), &index, |b, index| {
+            b.iter(|| {
+                let writer = index.clone();
+                let reader = index.clone();
+                run_mixed(
+                    move |i| writer.put(key(i * 7 % KEY_NUM), i as u64).unwrap(),
+                    move |t, i| assert!(reader.contains(&key((i * 13 + t) % KEY_NUM)).unwrap()),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+/// 经过 Engine 读写，包括`Storage`的读写及锁
+fn bench_engine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine_mixed_read_write");
+    for (name, index_type) in INDEX_TYPES {
+        let dir = TempDir::new().unwrap();
+        let engine = Arc::new(
+            Engine::new(Config {
+                dir_path: dir.path().to_path_buf(),
+                index_type,
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        for i in 0..KEY_NUM {
+            engine.set(key(i), b"value".to_vec()).unwrap();
+        }
+
+        group.bench_with_input(BenchmarkId::from_parameter(name), &engine, |b, engine| {
+            b.iter(|| {
+                let writer = engine.clone();
+                let reader = engine.clone();
+                run_mixed(
+                    move |i| {
+                        writer
+                            .set(key(i * 7 % KEY_NUM), b"new-value".to_vec())
+                            .unwrap()
+                    },
+                    move |t, i| {
+                        reader.get(key((i * 13 + t) % KEY_NUM)).unwrap();
+                    },
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_index, bench_engine);
+criterion_main!(benches);
@@ -0,0 +1,285 @@
+use std::{collections::HashMap, sync::atomic::Ordering};
+
+use parking_lot::Mutex;
+
+use crate::{
+    config::BatchConfig,
+    data::{
+        record::{key_with_seq, Record, RecordType},
+        storage::Storage,
+    },
+    error::{KvError, Result},
+    Engine,
+};
+
+/// 批量写入，提交后其中的全部数据原子性地生效
+pub struct WriteBatch<'a> {
+    pending: Mutex<HashMap<Vec<u8>, Record>>,
+    engine: &'a Engine,
+    config: BatchConfig,
+}
+
+impl Engine {
+    /// 创建一个批量写入
+    pub fn new_write_batch(&self, config: BatchConfig) -> WriteBatch<'_> {
+        WriteBatch {
+            pending: Mutex::new(HashMap::new()),
+            engine: self,
+            config,
+        }
+    }
+}
+
+impl WriteBatch<'_> {
+    /// 暂存 key，value 数据，其中 key 不为空
+    pub fn put<B: Into<Vec<u8>>>(&self, key: B, value: B) -> Result<()> {
+        let key = key.into();
+        if key.is_empty() {
+            return Err(KvError::InvalidKey);
+        }
+
+        let record = Record::new_set(key.clone(), value.into());
+        self.pending.lock().insert(key, record);
+        Ok(())
+    }
+
+    /// 暂存对 key 的删除操作
+    pub fn delete<B: Into<Vec<u8>>>(&self, key: B) -> Result<()> {
+        let key = key.into();
+        if key.is_empty() {
+            return Err(KvError::InvalidKey);
+        }
+
+        let mut pending = self.pending.lock();
+        // key在索引中不存在时，只需丢弃暂存的数据
+        if self.engine.index.get(&key)?.is_none() {
+            pending.remove(&key);
+            return Ok(());
+        }
+
+        let record = Record::new_remove(key.clone());
+        pending.insert(key, record);
+        Ok(())
+    }
+
+    /// 提交批量写入，全部`Record`写入后追加提交标记并更新索引
+    pub fn commit(&self) -> Result<()> {
+        let mut pending = self.pending.lock();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        if pending.len() > self.config.max_batch_num {
+            return Err(KvError::ExceedMaxBatchNum);
+        }
+
+        // 持有活跃文件的写锁直至索引更新完成，串行化批量写入的提交
+        let mut active_storage = self.engine.active_storage.write();
+        self.engine
+            .write_batch(&mut active_storage, &mut pending, self.config.sycn_write)
+    }
+}
+
+impl Engine {
+    /// 以批量写入的格式原子性地写入`records`并更新索引，写入后`records`被清空
+    ///
+    /// 调用方需持有活跃文件的写锁
+    pub(crate) fn write_batch(
+        &self,
+        active_storage: &mut Storage,
+        records: &mut HashMap<Vec<u8>, Record>,
+        sync: bool,
+    ) -> Result<()> {
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst) + 1;
+
+        // 写入带有序列号的记录
+        let mut positions = HashMap::with_capacity(records.len());
+        for (key, record) in records.iter() {
+            let record = Record {
+                key: key_with_seq(key, seq),
+                value: record.value.clone(),
+                record_type: record.record_type,
+                keyspace: record.keyspace,
+            };
+            let pos = self.append_record(active_storage, &record)?;
+            positions.insert(key.clone(), pos);
+        }
+
+        // 写入提交标记
+        let commit_pos = self.append_record(active_storage, &Record::new_batch_commit(seq))?;
+
+        // 提交时持久化
+        if sync {
+            active_storage.sync()?;
+        }
+
+        // 更新索引，提交标记、删除标记及被覆盖的`Record`均可被合并回收
+        let mut reclaimable_size = commit_pos.size as u64;
+        for (key, record) in records.drain() {
+            let Some(pos) = positions.remove(&key) else {
+                continue;
+            };
+            self.transactions.record(&key);
+            let old_pos = match record.record_type {
+                RecordType::Normal | RecordType::Expiring | RecordType::MergeOperand => {
+                    self.index.put(key, pos)?
+                }
+                RecordType::Remove => {
+                    reclaimable_size += pos.size as u64;
+                    self.index.delete(&key)?
+                }
+                RecordType::UnexpectCommand | RecordType::BatchCommit => None,
+            };
+            reclaimable_size += old_pos.map_or(0, |p| p.size as u64);
+        }
+        self.reclaimable_size
+            .fetch_add(reclaimable_size, Ordering::SeqCst);
+        self.maybe_checkpoint(active_storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, OpenOptions},
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+    };
+
+    use tempfile::TempDir;
+
+    use crate::{
+        config::{BatchConfig, IteratorConfig},
+        data::{
+            hint::hint_name_from_gen,
+            record::{key_with_seq, Record},
+            storage::storage_name_from_gen,
+        },
+        error::KvError,
+        test_util::open,
+    };
+
+    #[test]
+    fn committed_batch_survives_reopen() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine.set("k0", "old").unwrap();
+
+        let batch = engine.new_write_batch(BatchConfig::default());
+        batch.put("k1", "v1").unwrap();
+        batch.put("k2", "v2").unwrap();
+        batch.delete("k0").unwrap();
+        // 提交前不可见
+        assert!(matches!(engine.get("k1"), Err(KvError::InvalidKey)));
+        batch.commit().unwrap();
+        engine.close().unwrap();
+
+        let engine = open(&dir);
+        assert_eq!(engine.get("k1").unwrap(), "v1");
+        assert_eq!(engine.get("k2").unwrap(), "v2");
+        assert!(matches!(engine.get("k0"), Err(KvError::InvalidKey)));
+    }
+
+    #[test]
+    fn batch_without_commit_marker_is_ignored_on_reopen() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine.set("k0", "v0").unwrap();
+        let batch = engine.new_write_batch(BatchConfig::default());
+        batch.put("k1", "v1").unwrap();
+        batch.put("k0", "overwritten").unwrap();
+        batch.commit().unwrap();
+        engine.close().unwrap();
+
+        // 截去末尾的提交标记，模拟写入提交标记前崩溃
+        let marker_len = Record::new_batch_commit(1).encode().unwrap().len() as u64;
+        let gen_path = dir.path().join(storage_name_from_gen(0));
+        let fd = OpenOptions::new().write(true).open(&gen_path).unwrap();
+        fd.set_len(fd.metadata().unwrap().len() - marker_len)
+            .unwrap();
+        fs::remove_file(dir.path().join(hint_name_from_gen(0))).unwrap();
+
+        let engine = open(&dir);
+        assert!(matches!(engine.get("k1"), Err(KvError::InvalidKey)));
+        assert_eq!(engine.get("k0").unwrap(), "v0");
+
+        // 新的批量写入不会复用未提交批次的序列号
+        let batch = engine.new_write_batch(BatchConfig::default());
+        batch.put("k2", "v2").unwrap();
+        batch.commit().unwrap();
+        engine.close().unwrap();
+        let engine = open(&dir);
+        assert_eq!(engine.get("k2").unwrap(), "v2");
+        assert!(matches!(engine.get("k1"), Err(KvError::InvalidKey)));
+    }
+
+    #[test]
+    fn exceeding_max_batch_num_writes_nothing() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        let batch = engine.new_write_batch(BatchConfig {
+            max_batch_num: 1,
+            sycn_write: false,
+        });
+        batch.put("k1", "v1").unwrap();
+        batch.put("k2", "v2").unwrap();
+        assert!(matches!(batch.commit(), Err(KvError::ExceedMaxBatchNum)));
+        assert!(matches!(engine.get("k1"), Err(KvError::InvalidKey)));
+    }
+
+    #[test]
+    fn seq_is_encoded_in_key() {
+        let key = key_with_seq(b"key", 300);
+        let (parsed, seq) = crate::data::record::parse_seq_key(key).unwrap();
+        assert_eq!(parsed, b"key");
+        assert_eq!(seq, 300);
+    }
+
+    #[test]
+    fn snapshots_never_see_a_partial_batch() {
+        const KEYS: usize = 100;
+        const BATCHES: usize = 200;
+
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        let done = AtomicBool::new(false);
+
+        // 批次交替写入及删除全部 key，快照及迭代器中的 key 数量只能为0或`KEYS`
+        let assert_consistent = |count: usize| assert!(count == 0 || count == KEYS, "{}", count);
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        let snapshot = engine.snapshot().unwrap();
+                        let count = (0..KEYS)
+                            .filter(|k| {
+                                snapshot.contains_key(format!("k{}", k).as_bytes()).unwrap()
+                            })
+                            .count();
+                        assert_consistent(count);
+
+                        let mut iter = engine.iter(IteratorConfig::default()).unwrap();
+                        let mut count = 0;
+                        while let Some(key) = iter.next_key() {
+                            key.unwrap();
+                            count += 1;
+                        }
+                        assert_consistent(count);
+                    }
+                });
+            }
+
+            for i in 0..BATCHES {
+                let batch = engine.new_write_batch(BatchConfig::default());
+                for k in 0..KEYS {
+                    if i % 2 == 0 {
+                        batch.put(format!("k{}", k), i.to_string()).unwrap();
+                    } else {
+                        batch.delete(format!("k{}", k)).unwrap();
+                    }
+                }
+                batch.commit().unwrap();
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+    }
+}
@@ -0,0 +1,271 @@
+use std::{collections::BTreeMap, ops::Bound, time::Duration};
+
+use parking_lot::Mutex;
+use tinykv::{Engine, IteratorConfig, KvError};
+
+use crate::{
+    glob::{glob_match, literal_prefix},
+    resp::Reply,
+};
+
+/// `SCAN`未指定`COUNT`时每次检查的 key 数量
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// 保留的`SCAN`游标数量上限，超出时丢弃最早创建的游标
+const MAX_CURSORS: usize = 4096;
+
+/// 在 Engine 上执行命令，全部连接共享
+pub(crate) struct Server {
+    engine: Engine,
+    cursors: Mutex<Cursors>,
+}
+
+/// `SCAN`的游标，记录上一次返回的最后一个 key，下一次从其之后继续迭代
+///
+/// 迭代按 key 有序进行，扫描期间一直存在的 key 恰好被返回一次
+struct Cursors {
+    next_id: u64,
+    last_keys: BTreeMap<u64, Vec<u8>>,
+}
+
+impl Cursors {
+    fn insert(&mut self, last_key: Vec<u8>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.last_keys.insert(id, last_key);
+        if self.last_keys.len() > MAX_CURSORS {
+            self.last_keys.pop_first();
+        }
+        id
+    }
+}
+
+/// 命令执行后连接的状态
+pub(crate) enum Flow {
+    Continue,
+    /// 回复后关闭连接
+    Close,
+}
+
+impl Server {
+    pub(crate) fn new(engine: Engine) -> Self {
+        Self {
+            engine,
+            cursors: Mutex::new(Cursors {
+                // 游标 0 表示迭代的开始及结束
+                next_id: 1,
+                last_keys: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// 关闭 Engine，调用方需保证没有正在执行的命令
+    pub(crate) fn close(self) -> tinykv::Result<()> {
+        self.engine.close()
+    }
+
+    /// 执行`args`表示的命令，`args`不为空
+    pub(crate) fn execute(&self, args: &[Vec<u8>]) -> (Reply, Flow) {
+        let name = args[0].to_ascii_uppercase();
+        let args = &args[1..];
+        let reply = match name.as_slice() {
+            b"PING" => match args {
+                [] => Ok(Reply::Simple("PONG")),
+                [message] => Ok(Reply::Bulk(Some(message.clone()))),
+                _ => Err(wrong_args("ping")),
+            },
+            b"ECHO" => match args {
+                [message] => Ok(Reply::Bulk(Some(message.clone()))),
+                _ => Err(wrong_args("echo")),
+            },
+            b"QUIT" => return (Reply::Simple("OK"), Flow::Close),
+            b"GET" => self.get(args),
+            b"SET" => self.set(args),
+            b"DEL" => self.del(args),
+            b"EXISTS" => self.exists(args),
+            b"SCAN" => self.scan(args),
+            b"KEYS" => self.keys(args),
+            b"DBSIZE" => self.dbsize(args),
+            _ => Err(format!(
+                "ERR unknown command '{}'",
+                String::from_utf8_lossy(&name).to_lowercase()
+            )),
+        };
+        (reply.unwrap_or_else(Reply::Error), Flow::Continue)
+    }
+
+    fn get(&self, args: &[Vec<u8>]) -> CommandResult {
+        let [key] = args else {
+            return Err(wrong_args("get"));
+        };
+        match self.engine.get(key.clone()) {
+            Ok(value) => Ok(Reply::Bulk(Some(value.to_vec()))),
+            Err(KvError::InvalidKey) => Ok(Reply::Bulk(None)),
+            Err(e) => Err(engine_err(e)),
+        }
+    }
+
+    /// SET key value [EX seconds | PX milliseconds]
+    fn set(&self, args: &[Vec<u8>]) -> CommandResult {
+        let [key, value, options @ ..] = args else {
+            return Err(wrong_args("set"));
+        };
+        let ttl = match options {
+            [] => None,
+            [unit, amount] => {
+                let scale = match unit.to_ascii_uppercase().as_slice() {
+                    b"EX" => 1000,
+                    b"PX" => 1,
+                    _ => return Err(SYNTAX_ERR.into()),
+                };
+                let millis = parse_int(amount)
+                    .filter(|n| *n > 0)
+                    .and_then(|n| n.checked_mul(scale))
+                    .filter(|n| *n <= i64::MAX as u64)
+                    .ok_or("ERR invalid expire time in 'set' command")?;
+                Some(Duration::from_millis(millis))
+            }
+            _ => return Err(SYNTAX_ERR.into()),
+        };
+
+        match ttl {
+            Some(ttl) => self.engine.set_with_ttl(key.clone(), value.clone(), ttl),
+            None => self.engine.set(key.clone(), value.clone()),
+        }
+        .map_err(engine_err)?;
+        Ok(Reply::Simple("OK"))
+    }
+
+    /// 返回被删除的 key 的数量
+    fn del(&self, args: &[Vec<u8>]) -> CommandResult {
+        if args.is_empty() {
+            return Err(wrong_args("del"));
+        }
+        let mut deleted = 0;
+        for key in args {
+            match self.engine.delete(key.clone()) {
+                Ok(()) => deleted += 1,
+                Err(KvError::InvalidKey) => {}
+                Err(e) => return Err(engine_err(e)),
+            }
+        }
+        Ok(Reply::Integer(deleted))
+    }
+
+    /// 返回存在的 key 的数量，重复的 key 被重复计数
+    fn exists(&self, args: &[Vec<u8>]) -> CommandResult {
+        if args.is_empty() {
+            return Err(wrong_args("exists"));
+        }
+        let mut found = 0;
+        for key in args {
+            if self.engine.contains_key(key.clone()).map_err(engine_err)? {
+                found += 1;
+            }
+        }
+        Ok(Reply::Integer(found))
+    }
+
+    /// SCAN cursor [MATCH pattern] [COUNT count]
+    fn scan(&self, args: &[Vec<u8>]) -> CommandResult {
+        let [cursor, options @ ..] = args else {
+            return Err(wrong_args("scan"));
+        };
+        let cursor = parse_int(cursor).ok_or("ERR invalid cursor")?;
+
+        let mut pattern = None;
+        let mut count = DEFAULT_SCAN_COUNT;
+        for option in options.chunks(2) {
+            match option {
+                [name, value] if name.eq_ignore_ascii_case(b"MATCH") => pattern = Some(value),
+                [name, value] if name.eq_ignore_ascii_case(b"COUNT") => {
+                    count = parse_int(value).filter(|n| *n > 0).ok_or(SYNTAX_ERR)? as usize;
+                }
+                _ => return Err(SYNTAX_ERR.into()),
+            }
+        }
+
+        let lower_bound = match cursor {
+            0 => Bound::Unbounded,
+            id => match self.cursors.lock().last_keys.get(&id) {
+                Some(last_key) => Bound::Excluded(last_key.clone()),
+                None => return Err("ERR invalid cursor".into()),
+            },
+        };
+        let mut iter = self
+            .engine
+            .iter(IteratorConfig {
+                prefix: pattern.map(|p| literal_prefix(p)).unwrap_or_default(),
+                lower_bound,
+                limit: Some(count),
+                ..Default::default()
+            })
+            .map_err(engine_err)?;
+
+        let mut keys = Vec::new();
+        let mut scanned = 0;
+        let mut last_key = None;
+        while let Some(key) = iter.next_key() {
+            let key = key.map_err(engine_err)?;
+            scanned += 1;
+            if pattern.is_none_or(|p| glob_match(p, &key)) {
+                keys.push(Reply::Bulk(Some(key.clone())));
+            }
+            last_key = Some(key);
+        }
+        drop(iter);
+
+        // 本次检查的 key 不足`count`时迭代已结束
+        let next_cursor = match last_key {
+            Some(last_key) if scanned == count => self.cursors.lock().insert(last_key),
+            _ => 0,
+        };
+        Ok(Reply::Array(vec![
+            Reply::Bulk(Some(next_cursor.to_string().into_bytes())),
+            Reply::Array(keys),
+        ]))
+    }
+
+    /// KEYS pattern
+    fn keys(&self, args: &[Vec<u8>]) -> CommandResult {
+        let [pattern] = args else {
+            return Err(wrong_args("keys"));
+        };
+        let keys = self
+            .engine
+            .list_keys(literal_prefix(pattern))
+            .map_err(engine_err)?;
+        Ok(Reply::Array(
+            keys.into_iter()
+                .filter(|key| glob_match(pattern, key))
+                .map(|key| Reply::Bulk(Some(key)))
+                .collect(),
+        ))
+    }
+
+    /// 未过期的 key 的数量，只读取索引
+    fn dbsize(&self, args: &[Vec<u8>]) -> CommandResult {
+        if !args.is_empty() {
+            return Err(wrong_args("dbsize"));
+        }
+        // 服务器只使用默认 keyspace；与 Redis 相同，尚未被清理的过期 key 同样被计数
+        let stat = self.engine.stat().map_err(engine_err)?;
+        Ok(Reply::Integer(stat.key_num as i64))
+    }
+}
+
+type CommandResult = std::result::Result<Reply, String>;
+
+const SYNTAX_ERR: &str = "ERR syntax error";
+
+fn wrong_args(command: &str) -> String {
+    format!("ERR wrong number of arguments for '{}' command", command)
+}
+
+fn engine_err(e: KvError) -> String {
+    format!("ERR {}", e)
+}
+
+fn parse_int(arg: &[u8]) -> Option<u64> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
@@ -0,0 +1,179 @@
+/// 按 Redis 的 glob 语法匹配 key，支持`*`、`?`、`[...]`及`\`转义
+///
+/// `*`失配时回溯至最近一个`*`，时间复杂度为 O(pattern * key)
+pub(crate) fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    // 最近一个`*`之后的位置及其当前匹配到的 key 的位置
+    let mut star = None;
+    while k < key.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, k));
+            continue;
+        }
+        if let Some((len, true)) = match_one(&pattern[p..], key[k]) {
+            p += len;
+            k += 1;
+            continue;
+        }
+        let Some((star_p, star_k)) = star else {
+            return false;
+        };
+        // 令`*`多匹配一个字节
+        p = star_p;
+        k = star_k + 1;
+        star = Some((star_p, k));
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// 以`pattern`头部的单个元素匹配字节`c`，返回元素的长度及是否匹配
+///
+/// `pattern`为空或以`*`开头时返回`None`
+fn match_one(pattern: &[u8], c: u8) -> Option<(usize, bool)> {
+    match *pattern {
+        [] | [b'*', ..] => None,
+        [b'?', ..] => Some((1, true)),
+        [b'\\', escaped, ..] => Some((2, escaped == c)),
+        [b'[', ..] => Some(match_class(pattern, c)),
+        [literal, ..] => Some((1, literal == c)),
+    }
+}
+
+/// 匹配`[...]`字符集，支持`^`取反及`a-z`范围，缺少`]`时字符集延续至`pattern`末尾
+fn match_class(pattern: &[u8], c: u8) -> (usize, bool) {
+    let mut i = 1;
+    let negate = pattern.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    while i < pattern.len() && pattern[i] != b']' {
+        match pattern[i..] {
+            [b'\\', escaped, ..] => {
+                matched |= escaped == c;
+                i += 2;
+            }
+            [start, b'-', end, ..] if end != b']' => {
+                let (lo, hi) = if start <= end {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+                matched |= (lo..=hi).contains(&c);
+                i += 3;
+            }
+            [literal, ..] => {
+                matched |= literal == c;
+                i += 1;
+            }
+            [] => unreachable!(),
+        }
+    }
+    // 跳过`]`
+    let len = (i + 1).min(pattern.len());
+    (len, matched != negate)
+}
+
+/// `pattern`中第一个通配符之前的字面量，匹配的 key 均以其为前缀
+pub(crate) fn literal_prefix(pattern: &[u8]) -> Vec<u8> {
+    let mut prefix = Vec::new();
+    let mut i = 0;
+    while i < pattern.len() {
+        match pattern[i..] {
+            [b'*' | b'?' | b'[', ..] | [b'\\'] => break,
+            [b'\\', escaped, ..] => {
+                prefix.push(escaped);
+                i += 2;
+            }
+            [literal, ..] => {
+                prefix.push(literal);
+                i += 1;
+            }
+            [] => unreachable!(),
+        }
+    }
+    prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, key: &str) -> bool {
+        glob_match(pattern.as_bytes(), key.as_bytes())
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("user:*", "user:1"));
+        assert!(!matches("user:*", "users:1"));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(!matches("*a*b", "xxbxxa"));
+        // `*`失配后回溯
+        assert!(matches("*ab", "aab"));
+        assert!(matches("a*a*a", "aaaaa"));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-c]llo", "hbllo"));
+        // 反向的范围与正向相同
+        assert!(matches("h[c-a]llo", "hbllo"));
+        assert!(!matches("h[a-c]llo", "hdllo"));
+        // `-`位于`]`之前时为字面量
+        assert!(matches("a[x-]", "a-"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches(r"a\*", "a*"));
+        assert!(!matches(r"a\*", "ab"));
+        assert!(matches(r"\?\[", "?["));
+        assert!(matches(r"[\]]", "]"));
+        assert!(matches(r"[\-a]", "-"));
+        // 末尾单独的`\`为字面量
+        assert!(matches("a\\", "a\\"));
+    }
+
+    #[test]
+    fn unterminated_class_extends_to_the_end() {
+        assert!(matches("[abc", "a"));
+        assert!(!matches("[abc", "d"));
+        assert!(!matches("[abc", "ab"));
+        // 空的字符集仍需匹配一个字节，取反后匹配任意字节
+        assert!(!matches("x[", "x"));
+        assert!(!matches("x[", "xa"));
+        assert!(matches("[^", "a"));
+    }
+
+    #[test]
+    fn literal_prefixes() {
+        let prefix = |pattern: &str| literal_prefix(pattern.as_bytes());
+        assert_eq!(prefix("user:*"), b"user:");
+        assert_eq!(prefix("plain"), b"plain");
+        assert_eq!(prefix("a?c"), b"a");
+        assert_eq!(prefix("ab[cd]"), b"ab");
+        assert_eq!(prefix("*suffix"), b"");
+        assert_eq!(prefix(r"a\*b*"), b"a*b");
+        assert_eq!(prefix("a\\"), b"a");
+        // 前缀匹配的 key 总是以其为前缀
+        for pattern in ["user:*", r"a\*b*", "ab[cd]", "a\\"] {
+            for key in ["user:1", "a*bc", "abc", "a\\"] {
+                if matches(pattern, key) {
+                    assert!(key.as_bytes().starts_with(&prefix(pattern)));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! 以 Redis RESP2 协议通过 TCP 提供 Engine 的访问，可直接使用 redis-cli 等 Redis 客户端
+//!
+//! 支持的命令：GET、SET（EX/PX）、DEL、EXISTS、SCAN（MATCH/COUNT）、KEYS、DBSIZE、PING、ECHO、QUIT
+//!
+//! ```text
+//! tinykv-server [--addr 127.0.0.1:6379] [--dir tinykv-data] [--index btree] [--sync]
+//! ```
+//!
+//! 收到 ctrl-c 或 SIGTERM 时关闭全部连接，并关闭 Engine 后退出
+
+mod command;
+mod glob;
+mod resp;
+
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, BufWriter, ErrorKind, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use parking_lot::Mutex;
+
+use tinykv::{Config, Engine, IndexType};
+
+use command::{Flow, Server};
+use resp::{read_command, Reply};
+
+const DEFAULT_ADDR: &str = "127.0.0.1:6379";
+const DEFAULT_DIR: &str = "tinykv-data";
+
+const USAGE: &str = "usage: tinykv-server [--addr <host:port>] [--dir <path>] \
+                     [--index btree|art|skiplist|bptree|hash] [--sync]";
+
+struct Args {
+    addr: String,
+    config: Config,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut addr = DEFAULT_ADDR.to_string();
+    let mut config = Config {
+        dir_path: PathBuf::from(DEFAULT_DIR),
+        ..Default::default()
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--addr" => addr = value()?,
+            "--dir" => config.dir_path = PathBuf::from(value()?),
+            "--index" => {
+                config.index_type = match value()?.as_str() {
+                    "btree" => IndexType::BTree,
+                    "art" => IndexType::ART,
+                    "skiplist" => IndexType::SkipList,
+                    "bptree" => IndexType::BPlusTree,
+                    "hash" => IndexType::HashSharded,
+                    other => return Err(format!("unknown index type: {}", other)),
+                }
+            }
+            "--sync" => config.sync_write = true,
+            "-h" | "--help" => return Err(String::new()),
+            other => return Err(format!("unknown argument: {}", other)),
+        }
+    }
+    Ok(Args { addr, config })
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|e| {
+        if !e.is_empty() {
+            eprintln!("{}", e);
+        }
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    });
+
+    let engine = Engine::new(args.config).unwrap_or_else(|e| {
+        eprintln!("failed to open engine: {}", e);
+        process::exit(1);
+    });
+    let listener = TcpListener::bind(&args.addr).unwrap_or_else(|e| {
+        eprintln!("failed to listen on {}: {}", args.addr, e);
+        process::exit(1);
+    });
+    // 端口为 0 时由系统分配，输出实际监听的地址
+    let addr = listener
+        .local_addr()
+        .map_or(args.addr, |addr| addr.to_string());
+    eprintln!("tinykv-server listening on {}", addr);
+
+    // 收到 ctrl-c 或 SIGTERM 后停止接受连接，并连接自身以唤醒阻塞的 accept
+    let shutdown = Arc::new(AtomicBool::new(false));
+    {
+        let shutdown = shutdown.clone();
+        let addr = addr.clone();
+        ctrlc::set_handler(move || {
+            shutdown.store(true, Ordering::SeqCst);
+            let _ = TcpStream::connect(&addr);
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("failed to set signal handler: {}", e);
+            process::exit(1);
+        });
+    }
+
+    // 每个连接使用一个线程，Engine 的读写可以并发进行
+    let server = Arc::new(Server::new(engine));
+    let connections = Arc::new(Mutex::new(HashMap::new()));
+    let mut handles = Vec::new();
+    for (id, stream) in listener.incoming().enumerate() {
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        if let Ok(clone) = stream.try_clone() {
+            connections.lock().insert(id, clone);
+        }
+
+        let server = server.clone();
+        let connections = connections.clone();
+        handles.retain(|h: &JoinHandle<()>| !h.is_finished());
+        handles.push(thread::spawn(move || {
+            let peer = stream.peer_addr().ok();
+            if let Err(e) = serve(stream, &server) {
+                eprintln!("connection {:?} closed: {}", peer, e);
+            }
+            connections.lock().remove(&id);
+        }));
+    }
+
+    // 关闭全部连接，等待执行中的命令完成后关闭 Engine
+    for stream in connections.lock().values() {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    for handle in handles {
+        let _ = handle.join();
+    }
+    let Ok(server) = Arc::try_unwrap(server) else {
+        unreachable!("all connections have been closed");
+    };
+    if let Err(e) = server.close() {
+        eprintln!("failed to close engine: {}", e);
+        process::exit(1);
+    }
+    eprintln!("tinykv-server shut down");
+}
+
+/// 依次执行连接中的命令，流水线发送的命令在读取完毕后才统一写出回复
+fn serve(stream: TcpStream, server: &Server) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    loop {
+        let args = match read_command(&mut reader) {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            // 协议错误后无法定位下一条命令，回复错误后关闭连接
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                Reply::Error(format!("ERR Protocol error: {}", e)).write_to(&mut writer)?;
+                return writer.flush();
+            }
+            Err(e) => return Err(e),
+        };
+        if args.is_empty() {
+            continue;
+        }
+
+        let (reply, flow) = server.execute(&args);
+        reply.write_to(&mut writer)?;
+        if let Flow::Close = flow {
+            return writer.flush();
+        }
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+}
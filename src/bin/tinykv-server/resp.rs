@@ -0,0 +1,199 @@
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+
+/// 单个 bulk string 的最大长度，与 Redis 一致
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// 单个请求中参数数量的上限
+const MAX_ARGS: usize = 1024 * 1024;
+
+/// 一行请求头或内联命令的最大长度
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// RESP2 格式的回复
+pub(crate) enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    /// `None`表示 nil
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    pub(crate) fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        match self {
+            Reply::Simple(s) => write!(w, "+{}\r\n", s),
+            // 错误信息中不能包含换行
+            Reply::Error(e) => write!(w, "-{}\r\n", e.replace(['\r', '\n'], " ")),
+            Reply::Integer(n) => write!(w, ":{}\r\n", n),
+            Reply::Bulk(None) => w.write_all(b"$-1\r\n"),
+            Reply::Bulk(Some(data)) => {
+                write!(w, "${}\r\n", data.len())?;
+                w.write_all(data)?;
+                w.write_all(b"\r\n")
+            }
+            Reply::Array(items) => {
+                write!(w, "*{}\r\n", items.len())?;
+                items.iter().try_for_each(|item| item.write_to(w))
+            }
+        }
+    }
+}
+
+/// 读取一条命令，连接关闭时返回`None`
+///
+/// 支持 RESP 数组格式及以空白分隔的内联命令，格式错误时返回`ErrorKind::InvalidData`
+pub(crate) fn read_command(r: &mut impl BufRead) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let Some(line) = read_line(r)? else {
+        return Ok(None);
+    };
+
+    let Some(count) = line.strip_prefix(b"*") else {
+        // 内联命令
+        return Ok(Some(
+            line.split(u8::is_ascii_whitespace)
+                .filter(|arg| !arg.is_empty())
+                .map(<[u8]>::to_vec)
+                .collect(),
+        ));
+    };
+
+    let count = parse_len(count, MAX_ARGS)?;
+    let mut args = Vec::with_capacity(count.min(64));
+    for _ in 0..count {
+        let Some(line) = read_line(r)? else {
+            return Err(ErrorKind::UnexpectedEof.into());
+        };
+        let Some(len) = line.strip_prefix(b"$") else {
+            return Err(invalid_data(format!(
+                "expected '$', got '{}'",
+                String::from_utf8_lossy(&line[..line.len().min(1)])
+            )));
+        };
+        let len = parse_len(len, MAX_BULK_LEN)?;
+
+        // 参数及其末尾的 CRLF
+        let mut arg = vec![0; len + 2];
+        r.read_exact(&mut arg)?;
+        if !arg.ends_with(b"\r\n") {
+            return Err(invalid_data("bulk string is not terminated by CRLF"));
+        }
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+/// 读取以 CRLF 或 LF 结尾的一行，不包含行尾
+fn read_line(r: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    let read = Read::take(&mut *r, MAX_LINE_LEN as u64 + 1).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(if line.len() > MAX_LINE_LEN {
+            invalid_data("too big request line")
+        } else {
+            ErrorKind::UnexpectedEof.into()
+        });
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse_len(buf: &[u8], max: usize) -> io::Result<usize> {
+    std::str::from_utf8(buf)
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|len| *len <= max)
+        .ok_or_else(|| invalid_data("invalid length"))
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &[u8]) -> io::Result<Option<Vec<Vec<u8>>>> {
+        read_command(&mut &input[..])
+    }
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn encode(reply: &Reply) -> Vec<u8> {
+        let mut buf = Vec::new();
+        reply.write_to(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_arrays_and_inline_commands() {
+        let mut input = &b"*2\r\n$3\r\nGET\r\n$5\r\nk\r\ney\r\nSET  a\tb\nPING\r\n"[..];
+        assert_eq!(
+            read_command(&mut input).unwrap(),
+            Some(vec![b"GET".to_vec(), b"k\r\ney".to_vec()])
+        );
+        assert_eq!(
+            read_command(&mut input).unwrap(),
+            Some(args(&["SET", "a", "b"]))
+        );
+        assert_eq!(read_command(&mut input).unwrap(), Some(args(&["PING"])));
+        assert_eq!(read_command(&mut input).unwrap(), None);
+
+        assert_eq!(read(b"*0\r\n").unwrap(), Some(Vec::new()));
+        assert_eq!(read(b"*1\r\n$0\r\n\r\n").unwrap(), Some(vec![Vec::new()]));
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        for input in [
+            &b"*x\r\n"[..],
+            b"*-1\r\n",
+            b"*1\r\n:1\r\n",
+            b"*1\r\n$abc\r\n",
+            b"*1\r\n$3\r\nabcde\r\n",
+            b"*1\r\n$999999999999\r\n",
+        ] {
+            let err = read(input).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{:?}", input);
+        }
+
+        let long_line = vec![b'a'; MAX_LINE_LEN + 1];
+        assert_eq!(read(&long_line).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_requests_are_unexpected_eof() {
+        for input in [&b"*2\r\n$3\r\nGET\r\n"[..], b"*1\r\n$3\r\nGE", b"PING"] {
+            let err = read(input).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn writes_replies() {
+        assert_eq!(encode(&Reply::Simple("OK")), b"+OK\r\n");
+        assert_eq!(
+            encode(&Reply::Error("ERR bad\r\nline".to_string())),
+            b"-ERR bad  line\r\n"
+        );
+        assert_eq!(encode(&Reply::Integer(-3)), b":-3\r\n");
+        assert_eq!(encode(&Reply::Bulk(None)), b"$-1\r\n");
+        assert_eq!(
+            encode(&Reply::Array(vec![
+                Reply::Bulk(Some(b"a\r\nb".to_vec())),
+                Reply::Array(Vec::new()),
+            ])),
+            b"*2\r\n$4\r\na\r\nb\r\n*0\r\n"
+        );
+    }
+}
@@ -1,12 +1,18 @@
-use std::{env::temp_dir, path::PathBuf};
+use std::{env::temp_dir, ops::Bound, path::PathBuf, sync::Arc};
 
-use crate::index::IndexType;
+use crate::{fio::IOType, index::IndexType};
 
 pub struct Config {
     pub dir_path: PathBuf,
     pub storage_size: u64,
     pub index_type: IndexType,
     pub sync_write: bool,
+    /// 旧`Storage`文件所使用的IO类型
+    pub io_type: IOType,
+    /// 启动时构建索引遇到损坏的`Record`时的处理方式
+    pub recovery_mode: RecoveryMode,
+    /// `Engine::merge_value`所使用的合并操作符
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
 }
 
 impl Default for Config {
@@ -16,14 +22,61 @@ impl Default for Config {
             storage_size: 1024 * 1024 * 64, // 64MB
             index_type: IndexType::BTree,
             sync_write: false,
+            io_type: IOType::StandardFIO,
+            recovery_mode: RecoveryMode::Strict,
+            merge_operator: None,
         }
     }
 }
 
-#[derive(Default)]
+/// 合并操作符，读取时将 key 的操作数依次合并至其原有的 value
+pub trait MergeOperator: Send + Sync {
+    /// 将`operand`合并至 key 原有的 value，`existing`为`None`时 key 原本不存在
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8>;
+}
+
+impl<F> MergeOperator for F
+where
+    F: Fn(&[u8], Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync,
+{
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+        self(key, existing, operand)
+    }
+}
+
+/// 启动时对损坏`Record`的处理方式，活跃文件末尾写入中断的数据总会被截断
+///
+/// 只作用于启动时被扫描的文件，即活跃文件及缺少有效hint文件的旧文件；
+/// 从hint文件加载的旧文件不会在启动时校验，其`Record`在读取时校验crc，损坏时返回`KvError::InvalidCrc`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryMode {
+    /// 遇到损坏的`Record`时打开失败
+    Strict,
+    /// 跳过损坏的`Record`并记录日志
+    Lenient,
+}
+
 pub struct IteratorConfig {
     pub prefix: Vec<u8>,
     pub reverse: bool,
+    /// key 的下界，与`prefix`同时指定时取交集
+    pub lower_bound: Bound<Vec<u8>>,
+    /// key 的上界，与`prefix`同时指定时取交集
+    pub upper_bound: Bound<Vec<u8>>,
+    /// 从起始位置或`seek`的位置开始最多返回的数据条数
+    pub limit: Option<usize>,
+}
+
+impl Default for IteratorConfig {
+    fn default() -> Self {
+        Self {
+            prefix: Vec::new(),
+            reverse: false,
+            lower_bound: Bound::Unbounded,
+            upper_bound: Bound::Unbounded,
+            limit: None,
+        }
+    }
 }
 
 pub struct BatchConfig {
@@ -0,0 +1,348 @@
+use std::{ffi::OsStr, fs, io::ErrorKind, path::Path};
+
+use bytes::{Buf, BufMut};
+use prost::{
+    decode_length_delimiter, encode_length_delimiter,
+    encoding::{decode_varint, encode_varint},
+};
+
+use super::record::{parse_type, RecordType, DEFAULT_KEYSPACE, KEYSPACE_FLAG, NO_EXPIRE};
+use crate::error::{KvError, Result};
+
+const HINT_SUFFIX: &str = "hint";
+const HINT_SUFFIX_WITH_DOT: &str = ".hint";
+
+/// `Storage`中一条`Record`的索引信息，其中 key 包含序列号
+pub(crate) struct HintEntry {
+    pub(crate) record_type: RecordType,
+    /// 所属 keyspace 的ID
+    pub(crate) keyspace: u32,
+    pub(crate) key: Vec<u8>,
+    pub(crate) offset: u64,
+    pub(crate) size: u32,
+    /// 过期时间，不会过期的`Record`为`NO_EXPIRE`
+    pub(crate) expire_at: u64,
+}
+
+/// 记录`Storage`中全部`Record`的索引信息，用于启动时快速构建索引
+///
+/// | entry | ... | data size | crc |
+/// | ----- | --- | --------- | --- |
+/// | dyn   | dyn | 8         | 4   |
+#[derive(Default)]
+pub(crate) struct Hint {
+    buf: Vec<u8>,
+}
+
+impl Hint {
+    /// | type | key size | offset | record size | expire at | keyspace | key |
+    /// | ---- | -------- | ------ | ----------- | --------- | -------- | --- |
+    /// | 1    | 1 ~ 5    | 1 ~ 10 | 1 ~ 5       | 0 ~ 10    | 0 ~ 5    | dyn |
+    ///
+    /// 仅`RecordType::Expiring`及`RecordType::MergeOperand`的条目存储过期时间，
+    /// 仅非默认 keyspace 的条目存储 keyspace ID，type 的标记位与`Record`一致
+    pub(crate) fn push(
+        &mut self,
+        record_type: RecordType,
+        keyspace: u32,
+        key: &[u8],
+        offset: u64,
+        size: u32,
+        expire_at: u64,
+    ) {
+        let mut entry_type = record_type as u8;
+        if keyspace != DEFAULT_KEYSPACE {
+            entry_type |= KEYSPACE_FLAG;
+        }
+        self.buf.put_u8(entry_type);
+        // 向Vec写入不会失败
+        encode_length_delimiter(key.len(), &mut self.buf).unwrap();
+        encode_varint(offset, &mut self.buf);
+        encode_varint(size as u64, &mut self.buf);
+        if let RecordType::Expiring | RecordType::MergeOperand = record_type {
+            encode_varint(expire_at, &mut self.buf);
+        }
+        if keyspace != DEFAULT_KEYSPACE {
+            encode_varint(keyspace as u64, &mut self.buf);
+        }
+        self.buf.extend_from_slice(key);
+    }
+
+    /// 写入并持久化对应大小为`data_size`的`Storage`的hint文件
+    pub(crate) fn write_to(&self, hint_path: &Path, data_size: u64) -> Result<()> {
+        let mut buf = Vec::with_capacity(self.buf.len() + 8 + 4);
+        buf.extend_from_slice(&self.buf);
+        buf.put_u64(data_size);
+        buf.put_u32(crc32fast::hash(&buf));
+
+        fs::write(hint_path, &buf)?;
+        fs::File::open(hint_path)?.sync_all()?;
+        Ok(())
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+/// 读取hint文件，文件不存在、已损坏或与`Storage`大小不一致时返回`None`
+pub(crate) fn load_hint(hint_path: &Path, data_size: u64) -> Result<Option<Vec<HintEntry>>> {
+    let buf = match fs::read(hint_path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if buf.len() < 8 + 4 {
+        return Ok(None);
+    }
+
+    // 校验crc及对应的`Storage`大小
+    let (content, mut crc) = buf.split_at(buf.len() - 4);
+    if crc32fast::hash(content) != crc.get_u32() {
+        return Ok(None);
+    }
+    let (mut entries_buf, mut size) = content.split_at(content.len() - 8);
+    if size.get_u64() != data_size {
+        return Ok(None);
+    }
+
+    let mut entries = Vec::new();
+    while entries_buf.has_remaining() {
+        let (record_type, has_keyspace) = parse_type(entries_buf.get_u8());
+        let key_size = decode_length_delimiter(&mut entries_buf)?;
+        let offset = decode_varint(&mut entries_buf)?;
+        let size = decode_varint(&mut entries_buf)? as u32;
+        let expire_at = match record_type {
+            RecordType::Expiring | RecordType::MergeOperand => decode_varint(&mut entries_buf)?,
+            _ => NO_EXPIRE,
+        };
+        let keyspace = if has_keyspace {
+            decode_varint(&mut entries_buf)? as u32
+        } else {
+            DEFAULT_KEYSPACE
+        };
+        if entries_buf.remaining() < key_size {
+            return Err(KvError::ReadEOF);
+        }
+        let key = entries_buf[..key_size].to_vec();
+        entries_buf.advance(key_size);
+
+        entries.push(HintEntry {
+            record_type,
+            keyspace,
+            key,
+            offset,
+            size,
+            expire_at,
+        });
+    }
+    Ok(Some(entries))
+}
+
+/// 删除hint文件，文件不存在时忽略
+pub(crate) fn remove_hint(hint_path: &Path) -> Result<()> {
+    match fs::remove_file(hint_path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[inline]
+pub(crate) fn is_hint_file(hint_path: &Path) -> Result<u32> {
+    if hint_path.extension() != Some(HINT_SUFFIX.as_ref()) {
+        return Err(KvError::InvalidPath);
+    }
+
+    let Some(Ok(gen)) = hint_path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .map(|s| s.trim_end_matches(HINT_SUFFIX_WITH_DOT))
+        .map(str::parse::<u32>)
+    else {
+        return Err(KvError::InvalidPath);
+    };
+
+    Ok(gen)
+}
+
+#[inline]
+pub(crate) fn hint_name_from_gen(gen: u32) -> String {
+    format!("{:09}{}", gen, HINT_SUFFIX_WITH_DOT)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        data::storage::storage_name_from_gen,
+        error::KvError,
+        test_util::{config, key},
+        Engine,
+    };
+
+    fn open(dir: &TempDir) -> Engine {
+        Engine::new(Config {
+            storage_size: 1024,
+            ..config(dir)
+        })
+        .unwrap()
+    }
+
+    /// 写入足以轮转多个`Storage`的数据，返回写入了hint文件的旧文件的gen
+    fn write_rotated(dir: &TempDir) -> Vec<u32> {
+        let engine = open(dir);
+        for i in 0..100 {
+            engine
+                .set(key(i), format!("value-{}", i).into_bytes())
+                .unwrap();
+        }
+        engine.delete(key(0)).unwrap();
+        engine.close().unwrap();
+
+        let mut gens = fs::read_dir(dir.path())
+            .unwrap()
+            .filter_map(|entry| is_hint_file(&entry.unwrap().path()).ok())
+            .collect::<Vec<_>>();
+        gens.sort_unstable();
+        assert!(gens.len() > 2);
+        gens
+    }
+
+    fn assert_data(engine: &Engine) {
+        assert!(matches!(engine.get(key(0)), Err(KvError::InvalidKey)));
+        for i in 1..100 {
+            assert_eq!(engine.get(key(i)).unwrap(), format!("value-{}", i));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = TempDir::new().unwrap();
+        let hint_path = dir.path().join(hint_name_from_gen(1));
+        let mut hint = Hint::default();
+        hint.push(RecordType::Normal, DEFAULT_KEYSPACE, b"a", 0, 20, NO_EXPIRE);
+        hint.push(RecordType::Expiring, 7, b"b", 20, 30, 123_456);
+        hint.push(
+            RecordType::Remove,
+            DEFAULT_KEYSPACE,
+            b"c",
+            50,
+            10,
+            NO_EXPIRE,
+        );
+        hint.write_to(&hint_path, 60).unwrap();
+
+        let entries = load_hint(&hint_path, 60).unwrap().unwrap();
+        let entries = entries
+            .iter()
+            .map(|e| {
+                (
+                    e.record_type as u8,
+                    e.keyspace,
+                    e.key.as_slice(),
+                    e.offset,
+                    e.size,
+                    e.expire_at,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                (
+                    RecordType::Normal as u8,
+                    DEFAULT_KEYSPACE,
+                    b"a".as_slice(),
+                    0,
+                    20,
+                    NO_EXPIRE
+                ),
+                (
+                    RecordType::Expiring as u8,
+                    7,
+                    b"b".as_slice(),
+                    20,
+                    30,
+                    123_456
+                ),
+                (
+                    RecordType::Remove as u8,
+                    DEFAULT_KEYSPACE,
+                    b"c".as_slice(),
+                    50,
+                    10,
+                    NO_EXPIRE
+                ),
+            ]
+        );
+
+        // 与`Storage`大小不一致的hint文件已过时
+        assert!(load_hint(&hint_path, 61).unwrap().is_none());
+        assert!(load_hint(&dir.path().join(hint_name_from_gen(2)), 0)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn corrupted_hint_falls_back_to_scanning() {
+        let dir = TempDir::new().unwrap();
+        let gens = write_rotated(&dir);
+
+        // 翻转第一个hint文件中的一个字节
+        let hint_path = dir.path().join(hint_name_from_gen(gens[0]));
+        let mut buf = fs::read(&hint_path).unwrap();
+        buf[0] ^= 0xff;
+        fs::write(&hint_path, &buf).unwrap();
+
+        let engine = open(&dir);
+        assert_data(&engine);
+        drop(engine);
+
+        // 扫描后补写了有效的hint文件
+        let data_size = fs::metadata(dir.path().join(storage_name_from_gen(gens[0])))
+            .unwrap()
+            .len();
+        assert!(load_hint(&hint_path, data_size).unwrap().is_some());
+    }
+
+    #[test]
+    fn truncated_hint_falls_back_to_scanning() {
+        let dir = TempDir::new().unwrap();
+        let gens = write_rotated(&dir);
+
+        for (i, gen) in gens.iter().enumerate() {
+            let hint_path = dir.path().join(hint_name_from_gen(*gen));
+            let buf = fs::read(&hint_path).unwrap();
+            // 截断至不同的长度，包括空文件
+            fs::write(&hint_path, &buf[..buf.len() * i / gens.len()]).unwrap();
+        }
+        assert_data(&open(&dir));
+    }
+
+    #[test]
+    fn stale_hint_is_ignored() {
+        let dir = TempDir::new().unwrap();
+        let gens = write_rotated(&dir);
+        // 正常关闭时写入的活跃文件的hint
+        let hint_path = dir.path().join(hint_name_from_gen(gens[gens.len() - 1]));
+        let stale = fs::read(&hint_path).unwrap();
+
+        let engine = open(&dir);
+        engine.set(key(0), b"value-0".to_vec()).unwrap();
+        engine.close().unwrap();
+
+        // 恢复旧的hint，其记录的大小与活跃文件不一致
+        fs::write(&hint_path, stale).unwrap();
+        let data_size = fs::metadata(dir.path().join(storage_name_from_gen(gens[gens.len() - 1])))
+            .unwrap()
+            .len();
+        assert!(load_hint(&hint_path, data_size).unwrap().is_none());
+        let engine = open(&dir);
+        for i in 0..100 {
+            assert_eq!(engine.get(key(i)).unwrap(), format!("value-{}", i));
+        }
+    }
+}
@@ -1,2 +1,3 @@
+pub(crate) mod hint;
 pub(crate) mod record;
 pub(crate) mod storage;
@@ -1,13 +1,45 @@
-use bytes::BufMut;
-use prost::{encode_length_delimiter, length_delimiter_len};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut};
+use prost::{
+    decode_length_delimiter, encode_length_delimiter,
+    encoding::{decode_varint, encode_varint, encoded_len_varint},
+    length_delimiter_len,
+};
 
 use crate::error::Result;
 
+/// 非批量写入的`Record`所使用的序列号
+pub(crate) const NON_BATCH_SEQ: usize = 0;
+
+/// 批量写入提交标记`Record`所使用的key
+pub(crate) const BATCH_COMMIT_KEY: &[u8] = b"batch-commit";
+
+/// 默认 keyspace 的ID，其`Record`的 key 中不包含 keyspace ID
+pub(crate) const DEFAULT_KEYSPACE: u32 = 0;
+
+/// type 中表示 key 的头部为 keyspace ID 的标记位
+pub(crate) const KEYSPACE_FLAG: u8 = 0x80;
+
+/// 表示永不过期的过期时间
+pub(crate) const NO_EXPIRE: u64 = 0;
+
+/// 带有过期时间的`Record`中过期时间的长度
+const EXPIRE_LEN: usize = 8;
+
+/// 合并操作数`Record`中指向前一条`Record`的位置的长度：| flag | gen | offset |
+const PREV_POS_LEN: usize = 1 + 4 + 8;
+
 #[derive(Clone, Copy)]
 pub enum RecordType {
     UnexpectCommand = 0,
     Normal = 1,
     Remove = 2,
+    BatchCommit = 3,
+    /// 带有过期时间的写入，value 的头部为过期时间
+    Expiring = 4,
+    /// 合并操作数，value 的头部为过期时间及同一 key 的前一条`Record`的位置
+    MergeOperand = 5,
 }
 
 impl From<u8> for RecordType {
@@ -15,21 +47,44 @@ impl From<u8> for RecordType {
         match value {
             1 => Self::Normal,
             2 => Self::Remove,
+            3 => Self::BatchCommit,
+            4 => Self::Expiring,
+            5 => Self::MergeOperand,
             _ => Self::UnexpectCommand,
         }
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub(crate) struct RecordPos {
     pub(crate) gen: u32,
     pub(crate) offset: u64,
+    /// `Record`在磁盘中的实际长度
+    pub(crate) size: u32,
+    /// 过期时间，自UNIX纪元起的毫秒数，`NO_EXPIRE`表示永不过期
+    pub(crate) expire_at: u64,
+}
+
+impl RecordPos {
+    /// `pos`处的`Record`是否已过期
+    #[inline]
+    pub(crate) fn is_expired(&self) -> bool {
+        self.expire_at != NO_EXPIRE && self.expire_at <= now_millis()
+    }
+}
+
+/// 解析`Record`的 type，返回类型及 key 的头部是否为 keyspace ID
+#[inline]
+pub(crate) fn parse_type(value: u8) -> (RecordType, bool) {
+    ((value & !KEYSPACE_FLAG).into(), value & KEYSPACE_FLAG != 0)
 }
 
 pub(crate) struct Record {
     pub(crate) key: Vec<u8>,
     pub(crate) value: Vec<u8>,
     pub(crate) record_type: RecordType,
+    /// 所属 keyspace 的ID，不包含在`key`中
+    pub(crate) keyspace: u32,
 }
 
 impl Record {
@@ -38,6 +93,47 @@ impl Record {
             key,
             value,
             record_type: RecordType::Normal,
+            keyspace: DEFAULT_KEYSPACE,
+        }
+    }
+
+    /// 于`expire_at`过期的写入，过期时间以大端字节序存储于 value 的头部
+    pub(crate) fn new_expiring(key: Vec<u8>, value: Vec<u8>, expire_at: u64) -> Self {
+        let mut buf = Vec::with_capacity(EXPIRE_LEN + value.len());
+        buf.put_u64(expire_at);
+        buf.extend_from_slice(&value);
+        Self {
+            key,
+            value: buf,
+            record_type: RecordType::Expiring,
+            keyspace: DEFAULT_KEYSPACE,
+        }
+    }
+
+    /// | expire at | flag | prev gen | prev offset | operand |
+    /// | --------- | ---- | -------- | ----------- | ------- |
+    /// | 8         | 1    | 4        | 8           | dyn     |
+    ///
+    /// 合并至`prev`处的`Record`的操作数，`prev`为`None`时 key 原本不存在，
+    /// 过期时间与`prev`处的`Record`一致
+    pub(crate) fn new_merge_operand(
+        key: Vec<u8>,
+        operand: Vec<u8>,
+        prev: Option<(u32, u64)>,
+        expire_at: u64,
+    ) -> Self {
+        let mut buf = Vec::with_capacity(EXPIRE_LEN + PREV_POS_LEN + operand.len());
+        buf.put_u64(expire_at);
+        let (gen, offset) = prev.unwrap_or_default();
+        buf.put_u8(prev.is_some() as u8);
+        buf.put_u32(gen);
+        buf.put_u64(offset);
+        buf.extend_from_slice(&operand);
+        Self {
+            key,
+            value: buf,
+            record_type: RecordType::MergeOperand,
+            keyspace: DEFAULT_KEYSPACE,
         }
     }
 
@@ -46,60 +142,113 @@ impl Record {
             key,
             value: Vec::new(),
             record_type: RecordType::Remove,
+            keyspace: DEFAULT_KEYSPACE,
+        }
+    }
+
+    pub(crate) fn new_batch_commit(seq: usize) -> Self {
+        Self {
+            key: key_with_seq(BATCH_COMMIT_KEY, seq),
+            value: Vec::new(),
+            record_type: RecordType::BatchCommit,
+            keyspace: DEFAULT_KEYSPACE,
+        }
+    }
+
+    /// 将`Record`写入`keyspace`
+    pub(crate) fn with_keyspace(mut self, keyspace: u32) -> Self {
+        self.keyspace = keyspace;
+        self
+    }
+
+    /// `Record`的过期时间，不会过期的`Record`返回`NO_EXPIRE`
+    pub(crate) fn expire_at(&self) -> u64 {
+        match self.record_type {
+            RecordType::Expiring | RecordType::MergeOperand if self.value.len() >= EXPIRE_LEN => {
+                u64::from_be_bytes(self.value[..EXPIRE_LEN].try_into().unwrap())
+            }
+            _ => NO_EXPIRE,
+        }
+    }
+
+    /// 写入的 value，不包含过期时间
+    pub(crate) fn into_value(self) -> Vec<u8> {
+        match self.record_type {
+            RecordType::Expiring => self.value.get(EXPIRE_LEN..).unwrap_or_default().to_vec(),
+            _ => self.value,
         }
     }
-    /// | type | key size | value size | key  | value | crc |
-    /// | ---- | -------- | ---------- | ---- | ----- | --- |
-    /// | 1    | 1 ~ 5    | 1 ~ 5      | dyn  | dyn   | 4   |
+
+    /// 合并操作数`Record`的前一条`Record`的位置及操作数
+    pub(crate) fn into_operand(self) -> (Option<(u32, u64)>, Vec<u8>) {
+        let Some(mut buf) = self.value.get(EXPIRE_LEN..EXPIRE_LEN + PREV_POS_LEN) else {
+            return (None, Vec::new());
+        };
+        let has_prev = buf.get_u8() != 0;
+        let gen = buf.get_u32();
+        let offset = buf.get_u64();
+        let operand = self.value[EXPIRE_LEN + PREV_POS_LEN..].to_vec();
+        (has_prev.then_some((gen, offset)), operand)
+    }
+
+    /// | type | key size | value size | keyspace | key  | value | crc |
+    /// | ---- | -------- | ---------- | -------- | ---- | ----- | --- |
+    /// | 1    | 1 ~ 5    | 1 ~ 5      | 0 ~ 5    | dyn  | dyn   | 4   |
     ///
-    /// 序列化为大端字符序列
+    /// 序列化为大端字符序列，非默认 keyspace 的`Record`在 type 中设置标记位，
+    /// 其 keyspace ID 计入 key size
     pub(crate) fn encode(&self) -> Result<Vec<u8>> {
-        // 为 buf header 部分预留可能的最大值
-        // header_max = type + max(key size) + max(value size)
-        let mut buf = Vec::with_capacity(self.encoded_len());
-        buf.put_u8(self.record_type as u8);
-
-        // 计算并存储key size和value size
-        encode_length_delimiter(self.key.len(), &mut buf)?;
-        encode_length_delimiter(self.value.len(), &mut buf)?;
-        buf.extend_from_slice(&self.key);
-        buf.extend_from_slice(&self.value);
+        let mut buf = self.encode_without_crc()?;
 
         // 计算并存储CRC校验值
-        let mut hasher = crc32fast::Hasher::new();
-        hasher.update(&buf);
-        let crc = hasher.finalize();
+        let crc = crc32fast::hash(&buf);
         buf.put_u32(crc);
-        // self.crc = Some(crc);
 
         Ok(buf)
     }
 
     /// 获取目标`Record`的crc校验值
     pub(crate) fn target_crc(&mut self) -> Result<u32> {
+        Ok(crc32fast::hash(&self.encode_without_crc()?))
+    }
+
+    fn encode_without_crc(&self) -> Result<Vec<u8>> {
         // 为 buf header 部分预留可能的最大值
         // header_max = type + max(key size) + max(value size)
         let mut buf = Vec::with_capacity(self.encoded_len());
-        buf.put_u8(self.record_type as u8);
+        if self.keyspace == DEFAULT_KEYSPACE {
+            buf.put_u8(self.record_type as u8);
+        } else {
+            buf.put_u8(self.record_type as u8 | KEYSPACE_FLAG);
+        }
 
         // 计算并存储key size和value size
-        encode_length_delimiter(self.key.len(), &mut buf)?;
+        encode_length_delimiter(self.keyspace_len() + self.key.len(), &mut buf)?;
         encode_length_delimiter(self.value.len(), &mut buf)?;
+        if self.keyspace != DEFAULT_KEYSPACE {
+            encode_varint(self.keyspace as u64, &mut buf);
+        }
         buf.extend_from_slice(&self.key);
         buf.extend_from_slice(&self.value);
+        Ok(buf)
+    }
 
-        // 计算并存储CRC校验值
-        let mut hasher = crc32fast::Hasher::new();
-        hasher.update(&buf);
-        Ok(hasher.finalize())
+    /// key 头部的 keyspace ID 在磁盘中的长度
+    #[inline]
+    fn keyspace_len(&self) -> usize {
+        match self.keyspace {
+            DEFAULT_KEYSPACE => 0,
+            keyspace => encoded_len_varint(keyspace as u64),
+        }
     }
 
     /// `Record`在磁盘中的实际长度
     fn encoded_len(&self) -> usize {
+        let key_size = self.keyspace_len() + self.key.len();
         std::mem::size_of::<u8>()
-            + length_delimiter_len(self.key.len())
+            + length_delimiter_len(key_size)
             + length_delimiter_len(self.value.len())
-            + self.key.len()
+            + key_size
             + self.value.len()
             + 4
     }
@@ -107,6 +256,8 @@ impl Record {
 
 pub(crate) struct ReadRecordHeaderBuf {
     pub(crate) record_type: RecordType,
+    /// key 的头部是否为 keyspace ID
+    pub(crate) has_keyspace: bool,
     pub(crate) key_size: usize,
     pub(crate) value_size: usize,
 }
@@ -131,3 +282,83 @@ impl ReadRecordHeaderBuf {
             + 4
     }
 }
+
+/// 解析`buf`开头的header，返回其`Record`在磁盘中的长度，无法解析时返回`None`
+pub(crate) fn encoded_record_len(buf: &[u8]) -> Option<usize> {
+    let mut header = buf;
+    if !header.has_remaining() {
+        return None;
+    }
+    let (record_type, has_keyspace) = parse_type(header.get_u8());
+    if let RecordType::UnexpectCommand = record_type {
+        return None;
+    }
+    let (Ok(key_size), Ok(value_size)) = (
+        decode_length_delimiter(&mut header),
+        decode_length_delimiter(&mut header),
+    ) else {
+        return None;
+    };
+    if key_size == 0 {
+        return None;
+    }
+
+    Some(
+        ReadRecordHeaderBuf {
+            record_type,
+            has_keyspace,
+            key_size,
+            value_size,
+        }
+        .encoded_len(),
+    )
+}
+
+/// `buf`是否以一条完整且crc正确的`Record`开头
+pub(crate) fn is_valid_record(buf: &[u8]) -> bool {
+    let Some(len) = encoded_record_len(buf) else {
+        return false;
+    };
+    let Some(mut crc) = buf.get(len - 4..len) else {
+        return false;
+    };
+    crc32fast::hash(&buf[..len - 4]) == crc.get_u32()
+}
+
+/// 当前时间，自UNIX纪元起的毫秒数
+#[inline]
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// | seq    | key |
+/// | ------ | --- |
+/// | 1 ~ 10 | dyn |
+///
+/// 将序列号编码至key的头部
+pub(crate) fn key_with_seq(key: &[u8], seq: usize) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(length_delimiter_len(seq) + key.len());
+    // 向Vec写入不会失败
+    encode_length_delimiter(seq, &mut buf).unwrap();
+    buf.extend_from_slice(key);
+    buf
+}
+
+/// 从磁盘中读取的 key 中解析出 keyspace ID，`has_keyspace`为`false`时属于默认 keyspace
+pub(crate) fn split_keyspace(key: Vec<u8>, has_keyspace: bool) -> Result<(u32, Vec<u8>)> {
+    if !has_keyspace {
+        return Ok((DEFAULT_KEYSPACE, key));
+    }
+    let mut buf = key.as_slice();
+    let keyspace = decode_varint(&mut buf)? as u32;
+    Ok((keyspace, buf.to_vec()))
+}
+
+/// 从编码后的key中解析出原始key和序列号
+pub(crate) fn parse_seq_key(key: Vec<u8>) -> Result<(Vec<u8>, usize)> {
+    let mut buf = key.as_slice();
+    let seq = decode_length_delimiter(&mut buf)?;
+    Ok((buf.to_vec(), seq))
+}
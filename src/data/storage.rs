@@ -1,7 +1,10 @@
-use super::record::{ReadRecordHeaderBuf, Record, RecordType};
+use super::record::{
+    encoded_record_len, is_valid_record, parse_type, split_keyspace, ReadRecordHeaderBuf, Record,
+    RecordType,
+};
 use crate::{
     error::{KvError, Result},
-    fio::{self, new_file_io},
+    fio::{self, new_file_io, IOType},
 };
 
 use bytes::{Buf, BytesMut};
@@ -15,6 +18,8 @@ use std::{
 
 const STORAGE_SUFFIX: &str = "storage";
 const STORAGE_SUFFIX_WITH_DOT: &str = ".storage";
+/// 查找有效`Record`时每次读取的字节数
+const FIND_CHUNK_SIZE: usize = 64 * 1024;
 
 pub(crate) struct Storage {
     pub(crate) gen: u32,
@@ -23,11 +28,11 @@ pub(crate) struct Storage {
 }
 
 impl Storage {
-    /// 打开或初始化一个`Storage`
-    pub(crate) fn new(gen_path: &Path) -> Result<Self> {
+    /// 以指定的IO类型打开或初始化一个`Storage`
+    pub(crate) fn new(gen_path: &Path, io_type: IOType) -> Result<Self> {
         let gen = is_storage_file(gen_path)?;
         let offset = AtomicU64::new(0);
-        let fio = Box::new(new_file_io(gen_path)?);
+        let fio = new_file_io(gen_path, io_type)?;
 
         Ok(Self { gen, offset, fio })
     }
@@ -39,7 +44,7 @@ impl Storage {
         Ok(Self {
             gen: 0,
             offset: AtomicU64::new(0),
-            fio: Box::new(new_file_io(gen_path.as_path())?),
+            fio: new_file_io(gen_path.as_path(), IOType::StandardFIO)?,
         })
     }
 
@@ -50,16 +55,23 @@ impl Storage {
 
         // 计算剩余部分的偏移量并读取
         let mut kv_buf = BytesMut::zeroed(header_buf.key_size + header_buf.value_size + 4);
-        self.fio.read(&mut kv_buf, offset + header_len as u64)?;
+        if self.fio.read(&mut kv_buf, offset + header_len as u64)? < kv_buf.len() {
+            // 数据不完整
+            return Err(KvError::ReadEOF);
+        }
 
+        let (keyspace, key) = split_keyspace(
+            kv_buf.get(..header_buf.key_size).unwrap().to_vec(),
+            header_buf.has_keyspace,
+        )?;
         let mut target_record = Record {
-            key: kv_buf.get(..header_buf.key_size).unwrap().to_vec(),
-            // key: x.to_vec(),
+            key,
             value: kv_buf
                 .get(header_buf.key_size..kv_buf.len() - 4)
                 .unwrap()
                 .to_vec(),
             record_type: header_buf.record_type,
+            keyspace,
         };
 
         // 移动游标至最后4字节
@@ -75,19 +87,22 @@ impl Storage {
         }
     }
 
-    // 仅用于从storage中读取key，但未验证crc正确性
+    // 仅用于从storage中读取keyspace ID及key，但未验证crc正确性
     pub(crate) fn read_key_from_header(
         &self,
         offset: u64,
         header_buf: &ReadRecordHeaderBuf,
-    ) -> Result<Vec<u8>> {
+    ) -> Result<(u32, Vec<u8>)> {
         let header_len = header_buf.get_header_len();
 
         // 计算并获取key
         let mut key_buf = BytesMut::zeroed(header_buf.key_size);
-        self.fio.read(&mut key_buf, offset + header_len as u64)?;
+        if self.fio.read(&mut key_buf, offset + header_len as u64)? < key_buf.len() {
+            // 数据不完整
+            return Err(KvError::ReadEOF);
+        }
 
-        Ok(key_buf.into())
+        split_keyspace(key_buf.into(), header_buf.has_keyspace)
     }
 
     /// 读取`Record`中的header部分，包括recory type，key size，value size
@@ -97,7 +112,7 @@ impl Storage {
         self.fio.read(&mut header_buf, offset)?;
 
         // 获取Record类型
-        let record_type = header_buf.get_u8().into();
+        let (record_type, has_keyspace) = parse_type(header_buf.get_u8());
         if let RecordType::UnexpectCommand = record_type {
             return Err(KvError::ReadEOF);
         }
@@ -112,11 +127,51 @@ impl Storage {
 
         Ok(ReadRecordHeaderBuf {
             record_type,
+            has_keyspace,
             key_size,
             value_size,
         })
     }
 
+    /// 查找`[from, end)`内首个完整且crc正确的`Record`的偏移
+    ///
+    /// 用于判断损坏的数据之后是否仍存在有效的`Record`，每次读取`FIND_CHUNK_SIZE`字节，
+    /// 并从上次检查到的位置继续
+    pub(crate) fn find_valid_record(&self, from: u64, end: u64) -> Result<Option<u64>> {
+        let mut buf = vec![0; FIND_CHUNK_SIZE];
+        let mut start = from;
+        while start < end {
+            let size = FIND_CHUNK_SIZE.min((end - start) as usize);
+            let len = self.fio.read(&mut buf[..size], start)?;
+            if len == 0 {
+                break;
+            }
+
+            let chunk = &buf[..len];
+            for i in 0..len {
+                let offset = start + i as u64;
+                let Some(record_len) = encoded_record_len(&chunk[i..]) else {
+                    continue;
+                };
+                let found = if i + record_len <= len {
+                    is_valid_record(&chunk[i..])
+                } else if offset + record_len as u64 <= end {
+                    // 跨越读取边界的`Record`单独读取后校验
+                    let mut record_buf = vec![0; record_len];
+                    self.fio.read(&mut record_buf, offset)? == record_len
+                        && is_valid_record(&record_buf)
+                } else {
+                    false
+                };
+                if found {
+                    return Ok(Some(offset));
+                }
+            }
+            start += len as u64;
+        }
+        Ok(None)
+    }
+
     /// 将buf写入至当前的`Storage`文件中
     pub(crate) fn write(&self, buf: &[u8]) -> Result<usize> {
         let len = self.fio.write(buf)?;
@@ -142,8 +197,8 @@ impl Storage {
 }
 
 #[inline]
-fn is_storage_file(gen_path: &Path) -> Result<u32> {
-    if !gen_path.is_file() || gen_path.extension() != Some(STORAGE_SUFFIX.as_ref()) {
+pub(crate) fn is_storage_file(gen_path: &Path) -> Result<u32> {
+    if gen_path.extension() != Some(STORAGE_SUFFIX.as_ref()) {
         return Err(KvError::InvalidPath);
     }
 
@@ -1,23 +1,90 @@
-use std::{collections::HashMap, fs, path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::ErrorKind,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use bytes::Bytes;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use prost::decode_length_delimiter;
 
 use crate::{
-    config::Config,
+    config::{Config, RecoveryMode},
     data::{
-        record::{Record, RecordPos, RecordType},
-        storage::{storage_name_from_gen, Storage},
+        hint::{hint_name_from_gen, load_hint, Hint, HintEntry},
+        record::{
+            key_with_seq, now_millis, parse_seq_key, Record, RecordPos, RecordType,
+            DEFAULT_KEYSPACE, NON_BATCH_SEQ, NO_EXPIRE,
+        },
+        storage::{is_storage_file, storage_name_from_gen, Storage},
     },
     error::{KvError, Result},
-    index::{new_index, Index, IndexType},
+    fio::IOType,
+    index::{new_index, Checkpoint, Index},
+    keyspace::{indexes_by_id, IndexesById, Keyspaces},
+    merge::load_merge_files,
+    snapshot::Retired,
+    transaction::ActiveTransactions,
+    util::write_file_atomically,
 };
 
+const LOCK_FILE_NAME: &str = "tinykv.lock";
+const FORMAT_FILE_NAME: &str = "tinykv.format";
+
+/// 数据目录的格式版本，`Record`的 key 头部包含批量写入的序列号
+const FORMAT_VERSION: u32 = 1;
+
+/// Engine 的统计信息
+#[derive(Debug, Clone, Copy)]
+pub struct Stat {
+    /// 全部 keyspace 的索引中 key 的数量
+    pub key_num: usize,
+    /// `Storage`文件的数量，包括活跃文件
+    pub storage_num: usize,
+    /// 数据目录在磁盘中占用的字节数
+    pub disk_size: u64,
+    /// 可被合并回收的字节数估计值
+    pub reclaimable_size: u64,
+    /// 打开时从活跃文件末尾截断的写入中断数据的字节数
+    pub discarded_size: u64,
+}
+
 pub struct Engine {
     pub(crate) config: Config,
     pub(crate) active_storage: Arc<RwLock<Storage>>,
     pub(crate) older_storages: Arc<RwLock<HashMap<u32, Storage>>>,
+    /// 默认 keyspace 的索引
     pub(crate) index: Box<dyn Index>,
+    /// 全部命名 keyspace，修改时需先持有活跃文件的写锁
+    pub(crate) keyspaces: RwLock<Keyspaces>,
+    /// 活跃文件的hint，在活跃文件轮转时写入磁盘
+    pub(crate) active_hint: Mutex<Hint>,
+    /// 当前已使用的最大批量写入序列号
+    pub(crate) seq: AtomicUsize,
+    /// 可被合并回收的字节数，包括被覆盖、删除的`Record`及删除标记等
+    pub(crate) reclaimable_size: AtomicU64,
+    /// 保证同一时刻只有一个合并任务
+    pub(crate) merge_lock: Mutex<()>,
+    /// 存活的快照及迭代器共享的引用，合并后替换为新的引用
+    pub(crate) snapshot_pin: Mutex<Arc<()>>,
+    /// 已被合并、但仍被快照引用的`Storage`
+    pub(crate) retired_storages: RwLock<Vec<Retired>>,
+    /// 进行中的事务，用于检测写入冲突
+    pub(crate) transactions: ActiveTransactions,
+    /// 正在被合并的`Storage`中最大的gen，合并操作数不能指向这些`Storage`
+    pub(crate) merging_gen: Mutex<Option<u32>>,
+    /// 打开时截断的写入中断数据的字节数
+    discarded_size: u64,
+    /// 持有数据目录的排他锁，防止多个 Engine 同时打开同一目录
+    lock_file: File,
+    /// 是否已通过`close`关闭
+    closed: bool,
 }
 
 impl Engine {
@@ -26,14 +93,63 @@ impl Engine {
         if !config.dir_path.is_dir() {
             std::fs::create_dir_all(&config.dir_path)?;
         }
+        let lock_file = lock_dir(&config.dir_path)?;
+        check_format_version(&config.dir_path)?;
+
+        // 完成上次未完成的合并
+        let merged = load_merge_files(&config.dir_path)?;
+
+        let keyspaces = Keyspaces::open(&config.dir_path, config.index_type)?;
+        let index = new_index(config.index_type, &config.dir_path, DEFAULT_KEYSPACE)?;
+        let states = keyspaces.states();
+        let indexes = indexes_by_id(index.as_ref(), &states);
+
+        // 持久化索引中的位置在合并后失效，需要重新构建
+        let checkpoint = match common_checkpoint(&indexes)? {
+            Some(checkpoint) if !merged => Some(checkpoint),
+            _ => {
+                for index in indexes.values() {
+                    index.clear()?;
+                }
+                None
+            }
+        };
+
         // 获取目标目录下storage的集合
-        let mut storages = load_storages_sorted(&config.dir_path)?;
-        let index = build_index_from_storage(&mut storages, config.index_type)?;
+        let mut storages = load_storages_sorted(&config.dir_path, config.io_type)?;
+        let (seq, active_hint, reclaimable_size) = build_index_from_storage(
+            &config.dir_path,
+            &mut storages,
+            &indexes,
+            checkpoint,
+            config.recovery_mode,
+        )?;
+        drop(indexes);
 
         // gen最大的文件即就是活跃文件
         // 若集合为空，则初始化新的storage作为活跃文件
+        let mut discarded_size = 0;
         let active_storage = match storages.pop() {
-            Some(s) => s,
+            Some(s) => {
+                let gen_path = config.dir_path.join(storage_name_from_gen(s.gen));
+                let offset = s.get_offset();
+                drop(s);
+
+                // 截断写入中断的末尾数据
+                discarded_size = truncate_torn_tail(gen_path.as_path(), offset)?;
+                if discarded_size > 0 {
+                    tracing::warn!(
+                        "discarded {} bytes of torn write at the tail of {}",
+                        discarded_size,
+                        gen_path.display()
+                    );
+                }
+
+                // 活跃文件需要写入，使用标准文件IO重新打开
+                let active = Storage::new(gen_path.as_path(), IOType::StandardFIO)?;
+                active.set_offset(offset);
+                active
+            }
             None => Storage::init_zero(&config.dir_path)?,
         };
 
@@ -44,115 +160,427 @@ impl Engine {
 
         Ok(Self {
             index,
+            keyspaces: RwLock::new(keyspaces),
             active_storage: Arc::new(RwLock::new(active_storage)),
             older_storages: Arc::new(RwLock::new(older_storages)),
+            active_hint: Mutex::new(active_hint),
+            seq: AtomicUsize::new(seq),
+            reclaimable_size: AtomicU64::new(reclaimable_size),
+            merge_lock: Mutex::new(()),
+            snapshot_pin: Mutex::new(Arc::new(())),
+            retired_storages: RwLock::new(Vec::new()),
+            transactions: ActiveTransactions::default(),
+            merging_gen: Mutex::new(None),
+            discarded_size,
+            lock_file,
+            closed: false,
             config,
         })
     }
 
     /// 存储 key，value 数据，其中 key 不为空
     pub fn set<B: Into<Vec<u8>>>(&self, key: B, value: B) -> Result<()> {
+        self.set_in(None, key.into(), value.into(), None)
+    }
+
+    /// 存储 key，value 数据，并在`ttl`后过期
+    pub fn set_with_ttl<B: Into<Vec<u8>>>(&self, key: B, value: B, ttl: Duration) -> Result<()> {
+        self.set_in(None, key.into(), value.into(), Some(ttl))
+    }
+
+    /// 获取 key 的剩余存活时间，永不过期时返回`None`
+    pub fn ttl<B: Into<Vec<u8>>>(&self, key: B) -> Result<Option<Duration>> {
+        let key = key.into();
+        let Some(pos) = self.index.get(&key)?.filter(|pos| !pos.is_expired()) else {
+            return Err(KvError::InvalidKey);
+        };
+        Ok(match pos.expire_at {
+            NO_EXPIRE => None,
+            expire_at => Some(Duration::from_millis(
+                expire_at.saturating_sub(now_millis()),
+            )),
+        })
+    }
+
+    /// 仅当 key 当前的 value 为`expected`时将其更新为`new`，返回是否更新成功
+    ///
+    /// `expected`为`None`表示 key 不存在，`new`为`None`表示删除 key；
+    /// 比较及写入期间持有活跃文件的写锁，与其他写入互斥
+    pub fn compare_and_swap<B: Into<Vec<u8>>>(
+        &self,
+        key: B,
+        expected: Option<&[u8]>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool> {
+        let key = key.into();
+        if key.is_empty() {
+            return Err(KvError::InvalidKey);
+        }
+
+        let mut active_storage = self.active_storage.write();
+        let current = match self.index.get(&key)?.filter(|pos| !pos.is_expired()) {
+            Some(pos) => {
+                Some(self.read_value(&active_storage, &self.older_storages.read(), &pos)?)
+            }
+            None => None,
+        };
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+
+        match new {
+            Some(value) => {
+                let record = Record::new_set(key_with_seq(&key, NON_BATCH_SEQ), value);
+                self.put_record(&mut active_storage, self.index.as_ref(), key, &record)?;
+            }
+            // key 不存在时无需写入删除标记
+            None if current.is_none() => {}
+            None => self.remove_record(
+                &mut active_storage,
+                self.index.as_ref(),
+                DEFAULT_KEYSPACE,
+                key,
+            )?,
+        }
+        Ok(true)
+    }
+
+    /// 仅当 key 不存在时存储 key，value 数据，返回是否写入成功
+    pub fn set_if_absent<B: Into<Vec<u8>>>(&self, key: B, value: B) -> Result<bool> {
+        self.compare_and_swap(key, None, Some(value.into()))
+    }
+
+    /// 将`operand`合并至 key 的 value，仅追加操作数而无需读取原有的 value
+    ///
+    /// 读取时由`Config::merge_operator`依次合并全部操作数，合并`Storage`时操作数被合并为完整的 value
+    pub fn merge_value<B: Into<Vec<u8>>>(&self, key: B, operand: B) -> Result<()> {
         let key = key.into();
         if key.is_empty() {
             return Err(KvError::InvalidKey);
         }
-        let value = value.into();
-        let record = Record::new_set(key, value);
+        let Some(operator) = &self.config.merge_operator else {
+            return Err(KvError::MergeOperatorMissing);
+        };
+        let operand = operand.into();
+        let seq_key = key_with_seq(&key, NON_BATCH_SEQ);
+
+        let mut active_storage = self.active_storage.write();
+        let record = match self.index.get(&key)?.filter(|pos| !pos.is_expired()) {
+            // 前一条`Record`所在的`Storage`正在被合并，合并后将被删除，需直接写入合并后的 value
+            Some(pos) if self.merging_gen.lock().is_some_and(|gen| pos.gen <= gen) => {
+                let existing =
+                    self.read_value(&active_storage, &self.older_storages.read(), &pos)?;
+                let value = operator.merge(&key, Some(&existing), &operand);
+                match pos.expire_at {
+                    NO_EXPIRE => Record::new_set(seq_key, value),
+                    expire_at => Record::new_expiring(seq_key, value, expire_at),
+                }
+            }
+            Some(pos) => Record::new_merge_operand(
+                seq_key,
+                operand,
+                Some((pos.gen, pos.offset)),
+                pos.expire_at,
+            ),
+            None => Record::new_merge_operand(seq_key, operand, None, NO_EXPIRE),
+        };
+        self.put_record(&mut active_storage, self.index.as_ref(), key, &record)
+    }
 
+    /// 写入非批量写入的`Record`并更新其所属 keyspace 的`index`，调用方需持有活跃文件的写锁
+    pub(crate) fn put_record(
+        &self,
+        active_storage: &mut Storage,
+        index: &dyn Index,
+        key: Vec<u8>,
+        record: &Record,
+    ) -> Result<()> {
         // 写入记录
-        let pos = self.append_record(&record)?;
+        let pos = self.append_record(active_storage, record)?;
 
-        // 更新索引
-        self.index.put(record.key, pos);
-        Ok(())
+        // 更新索引，被覆盖的`Record`可被合并回收；事务只读写默认 keyspace
+        if record.keyspace == DEFAULT_KEYSPACE {
+            self.transactions.record(&key);
+        }
+        if let Some(old_pos) = index.put(key, pos)? {
+            self.add_reclaimable_size(old_pos.size as u64);
+        }
+        self.maybe_checkpoint(active_storage)
+    }
+
+    /// 写入删除标记并从`keyspace`的`index`中删除 key，调用方需持有活跃文件的写锁
+    pub(crate) fn remove_record(
+        &self,
+        active_storage: &mut Storage,
+        index: &dyn Index,
+        keyspace: u32,
+        key: Vec<u8>,
+    ) -> Result<()> {
+        // 写入记录
+        let record = Record::new_remove(key_with_seq(&key, NON_BATCH_SEQ)).with_keyspace(keyspace);
+        let pos = self.append_record(active_storage, &record)?;
+
+        // 更新索引，删除标记及被删除的`Record`均可被合并回收
+        if keyspace == DEFAULT_KEYSPACE {
+            self.transactions.record(&key);
+        }
+        let old_size = index.delete(&key)?.map_or(0, |p| p.size as u64);
+        self.add_reclaimable_size(pos.size as u64 + old_size);
+        self.maybe_checkpoint(active_storage)
     }
 
     /// 根据 key 获取对应的数据
     pub fn get<B: Into<Vec<u8>>>(&self, key: B) -> Result<Bytes> {
         let key = key.into();
+        self.get_from(self.index.as_ref(), &key)
+    }
+
+    /// 根据`index`中 key 的位置获取对应的数据
+    pub(crate) fn get_from(&self, index: &dyn Index, key: &[u8]) -> Result<Bytes> {
         if key.is_empty() {
             return Err(KvError::InvalidKey);
         }
 
-        let Some(pos) = self.index.get(&key) else {
-            // key在索引中不存在
-            return Err(KvError::InvalidKey);
+        // key在索引中不存在或已过期
+        let lookup = || -> Result<RecordPos> {
+            index
+                .get(key)?
+                .filter(|pos| !pos.is_expired())
+                .ok_or(KvError::InvalidKey)
         };
 
-        self.read_value_from_pos(&pos)
+        // 查找索引时不持有`Storage`的锁，只在读取时持有读锁
+        let mut pos = lookup()?;
+        loop {
+            {
+                let active_storage = self.active_storage.read();
+                let older_storages = self.older_storages.read();
+                if active_storage.gen == pos.gen || older_storages.contains_key(&pos.gen) {
+                    return self.read_value(&active_storage, &older_storages, &pos);
+                }
+            }
+
+            // `Storage`在查找后被合并移除，移除前索引中的位置已被更新
+            match lookup()? {
+                next if next != pos => pos = next,
+                _ => return Err(KvError::InvalidKey),
+            }
+        }
+    }
+
+    /// key 是否存在且未过期，只读取索引
+    pub fn contains_key<B: Into<Vec<u8>>>(&self, key: B) -> Result<bool> {
+        let key = key.into();
+        Ok(self.index.get(&key)?.is_some_and(|pos| !pos.is_expired()))
     }
 
     /// 根据 key 删除对应的数据
     pub fn delete<B: Into<Vec<u8>>>(&self, key: B) -> Result<()> {
-        let key = key.into();
-        if key.is_empty() {
-            return Err(KvError::InvalidKey);
+        self.delete_in(None, key.into())
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.active_storage.read().sync()
+    }
+
+    /// 获取 Engine 的统计信息
+    pub fn stat(&self) -> Result<Stat> {
+        let storage_num = self.older_storages.read().len() + 1;
+
+        let mut disk_size = 0;
+        for entry in fs::read_dir(&self.config.dir_path)? {
+            let metadata = entry?.metadata()?;
+            if metadata.is_file() {
+                disk_size += metadata.len();
+            }
         }
 
-        // 先在索引中查找是否存在key
-        if self.index.get(&key).is_none() {
-            return Err(KvError::InvalidKey);
+        let mut key_num = self.index.len()?;
+        for state in self.keyspaces.read().states() {
+            key_num += state.index.len()?;
         }
 
-        let record = Record::new_remove(key);
+        Ok(Stat {
+            key_num,
+            storage_num,
+            disk_size,
+            reclaimable_size: self.reclaimable_size.load(Ordering::SeqCst),
+            discarded_size: self.discarded_size,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn add_reclaimable_size(&self, size: u64) {
+        self.reclaimable_size.fetch_add(size, Ordering::SeqCst);
+    }
 
-        // 写入记录
-        self.append_record(&record)?;
+    /// 关闭 Engine，持久化数据并写入活跃文件的hint文件后释放数据目录的锁
+    ///
+    /// 与`Drop`不同，关闭过程中的错误将返回给调用方
+    pub fn close(mut self) -> Result<()> {
+        self.shutdown()?;
+        self.closed = true;
+        Ok(())
+    }
 
-        // 更新索引
-        self.index.delete(&record.key);
+    fn shutdown(&self) -> Result<()> {
+        let active_storage = self.active_storage.write();
+        active_storage.sync()?;
+
+        let hint_path = self
+            .config
+            .dir_path
+            .join(hint_name_from_gen(active_storage.gen));
+        self.active_hint
+            .lock()
+            .write_to(&hint_path, active_storage.get_offset())?;
+        self.save_checkpoint(&active_storage)?;
+
+        self.lock_file.unlock()?;
         Ok(())
     }
 
-    pub fn sync(&self) -> Result<()> {
-        self.active_storage.read().sync()
+    /// 持久化索引的未持久化修改过多时保存检查点，调用方需持有活跃文件的写锁
+    #[inline]
+    pub(crate) fn maybe_checkpoint(&self, active_storage: &Storage) -> Result<()> {
+        let due = self.index.checkpoint_due()
+            || self
+                .keyspaces
+                .read()
+                .states()
+                .iter()
+                .any(|state| state.index.checkpoint_due());
+        if due {
+            self.save_checkpoint(active_storage)?;
+        }
+        Ok(())
     }
 
+    /// 以活跃文件当前的末尾为检查点持久化全部 keyspace 的索引
+    ///
+    /// 调用方需持有活跃文件的写锁，且此前写入的`Record`均已应用至索引
+    pub(crate) fn save_checkpoint(&self, active_storage: &Storage) -> Result<()> {
+        // 检查点之前的数据需先于索引落盘
+        active_storage.sync()?;
+        let checkpoint = Checkpoint {
+            gen: active_storage.gen,
+            offset: active_storage.get_offset(),
+            seq: self.seq.load(Ordering::SeqCst),
+            reclaimable_size: self.reclaimable_size.load(Ordering::SeqCst),
+        };
+        self.index.save_checkpoint(&checkpoint)?;
+        for state in self.keyspaces.read().states() {
+            state.index.save_checkpoint(&checkpoint)?;
+        }
+        Ok(())
+    }
+
+    /// 读取`pos`处的value，快照中的位置可能位于已被合并的`Storage`中
     pub(crate) fn read_value_from_pos(&self, pos: &RecordPos) -> Result<Bytes> {
         let active_storage = self.active_storage.read();
-        if active_storage.gen == pos.gen {
+        let older_storages = self.older_storages.read();
+        self.read_value(&active_storage, &older_storages, pos)
+    }
+
+    /// 读取`pos`处的value，合并操作数需读取同一 key 之前的`Record`并依次合并
+    fn read_value(
+        &self,
+        active_storage: &Storage,
+        older_storages: &HashMap<u32, Storage>,
+        pos: &RecordPos,
+    ) -> Result<Bytes> {
+        let record = self.read_record_at(active_storage, older_storages, pos.gen, pos.offset)?;
+        if !matches!(record.record_type, RecordType::MergeOperand) {
+            return Ok(record.into_value().into());
+        }
+        let Some(operator) = &self.config.merge_operator else {
+            return Err(KvError::MergeOperatorMissing);
+        };
+
+        // 沿前一条`Record`的位置收集操作数，直至 key 原有的 value
+        let (key, _) = parse_seq_key(record.key.clone())?;
+        let mut operands = Vec::new();
+        let mut existing = None;
+        let mut next = Some(record);
+        while let Some(record) = next.take() {
+            match record.record_type {
+                RecordType::MergeOperand => {
+                    let (prev, operand) = record.into_operand();
+                    operands.push(operand);
+                    if let Some((gen, offset)) = prev {
+                        next = Some(self.read_record_at(
+                            active_storage,
+                            older_storages,
+                            gen,
+                            offset,
+                        )?);
+                    }
+                }
+                RecordType::Normal | RecordType::Expiring => existing = Some(record.into_value()),
+                _ => {}
+            }
+        }
+
+        let value = operands.iter().rev().fold(existing, |existing, operand| {
+            Some(operator.merge(&key, existing.as_deref(), operand))
+        });
+        Ok(value.unwrap_or_default().into())
+    }
+
+    /// 从`gen`对应的`Storage`中读取`Record`，包括仍被快照引用的已合并`Storage`
+    fn read_record_at(
+        &self,
+        active_storage: &Storage,
+        older_storages: &HashMap<u32, Storage>,
+        gen: u32,
+        offset: u64,
+    ) -> Result<Record> {
+        if active_storage.gen == gen {
             // 若key在活跃文件中
-            return Ok(active_storage.read_record(pos.offset)?.value.into());
+            return active_storage.read_record(offset);
         }
 
         // 若key在旧文件中
-        let older_storages = self.older_storages.read();
-        match older_storages.get(&pos.gen) {
-            Some(storage) => Ok(storage.read_record(pos.offset)?.value.into()),
+        if let Some(storage) = older_storages.get(&gen) {
+            return storage.read_record(offset);
+        }
+
+        let retired = self.retired_storages.read();
+        match retired.iter().find_map(|r| r.storages.get(&gen)) {
+            Some(storage) => storage.read_record(offset),
             None => Err(KvError::InvalidKey),
         }
     }
 
     /// 追加写数据到活跃文件中
-    pub(crate) fn append_record(&self, record: &Record) -> Result<RecordPos> {
+    ///
+    /// 调用方需持有活跃文件的写锁直至索引更新完成，
+    /// 以保证合并时所有已写入的`Record`均已被索引
+    pub(crate) fn append_record(
+        &self,
+        active_storage: &mut Storage,
+        record: &Record,
+    ) -> Result<RecordPos> {
         let record_data = record.encode()?;
-
-        let mut active_storage = self.active_storage.write();
-        let offset = active_storage.get_offset();
+        let mut offset = active_storage.get_offset();
 
         // 判断`Storage`文件是否达到阈值
         if offset + record_data.len() as u64 > self.config.storage_size {
-            // 先持久化数据
-            active_storage.sync()?;
-
-            let old_gen = active_storage.gen;
-            // 初始化新的活跃文件
-            let file_name = self
-                .config
-                .dir_path
-                .join(storage_name_from_gen(old_gen + 1));
-            *active_storage = Storage::new(file_name.as_path())?;
-
-            // 将旧的活跃文件放入map中
-            let old_gen_path = self.config.dir_path.join(storage_name_from_gen(old_gen));
-            let older_storage = Storage::new(old_gen_path.as_path())?;
-
-            let mut older_storages = self.older_storages.write();
-            older_storages.insert(older_storage.gen, older_storage);
+            let new_gen = active_storage.gen + 1;
+            self.rotate_active_storage(active_storage, new_gen)?;
+            offset = active_storage.get_offset();
         }
 
         // 写入记录
         active_storage.write(&record_data)?;
+        self.active_hint.lock().push(
+            record.record_type,
+            record.keyspace,
+            &record.key,
+            offset,
+            record_data.len() as u32,
+            record.expire_at(),
+        );
 
         // 写时持久化
         if self.config.sync_write {
@@ -162,70 +590,1001 @@ impl Engine {
         Ok(RecordPos {
             gen: active_storage.gen,
             offset,
+            size: record_data.len() as u32,
+            expire_at: record.expire_at(),
         })
     }
+
+    /// 持久化当前的活跃文件，并以`new_gen`初始化新的活跃文件
+    pub(crate) fn rotate_active_storage(
+        &self,
+        active_storage: &mut Storage,
+        new_gen: u32,
+    ) -> Result<()> {
+        // 先持久化数据
+        active_storage.sync()?;
+
+        // 写入旧的活跃文件的hint文件
+        let old_gen = active_storage.gen;
+        let old_offset = active_storage.get_offset();
+        let mut active_hint = self.active_hint.lock();
+        let hint_path = self.config.dir_path.join(hint_name_from_gen(old_gen));
+        active_hint.write_to(&hint_path, active_storage.get_offset())?;
+        active_hint.clear();
+        // 初始化新的活跃文件
+        let file_name = self.config.dir_path.join(storage_name_from_gen(new_gen));
+        *active_storage = Storage::new(file_name.as_path(), IOType::StandardFIO)?;
+
+        // 将旧的活跃文件以只读方式放入map中
+        let old_gen_path = self.config.dir_path.join(storage_name_from_gen(old_gen));
+        let older_storage = Storage::new(old_gen_path.as_path(), self.config.io_type)?;
+        older_storage.set_offset(old_offset);
+
+        let mut older_storages = self.older_storages.write();
+        older_storages.insert(older_storage.gen, older_storage);
+        Ok(())
+    }
 }
 
 impl Drop for Engine {
     fn drop(&mut self) {
-        match self.active_storage.write().sync() {
-            Ok(_) => {}
-            Err(e) => tracing::warn!("{}", e),
+        if self.closed {
+            return;
+        }
+        if let Err(e) = self.shutdown() {
+            tracing::warn!("{}", e);
+        }
+    }
+}
+
+/// 获取数据目录的排他锁
+fn lock_dir(dir_path: &Path) -> Result<File> {
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir_path.join(LOCK_FILE_NAME))?;
+    match lock_file.try_lock() {
+        Ok(()) => Ok(lock_file),
+        Err(TryLockError::WouldBlock) => Err(KvError::DirectoryLocked),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// 校验数据目录的格式版本，新的数据目录写入当前版本
+///
+/// 缺少版本文件但存在数据的目录由引入版本之前的格式写入，其 key 中不包含序列号，无法读取
+fn check_format_version(dir_path: &Path) -> Result<()> {
+    let format_path = dir_path.join(FORMAT_FILE_NAME);
+    match fs::read_to_string(&format_path) {
+        Ok(content) => {
+            let version = content
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| KvError::CorruptedFile(format_path))?;
+            if version != FORMAT_VERSION {
+                return Err(KvError::UnsupportedVersion(version));
+            }
+            return Ok(());
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    for entry in fs::read_dir(dir_path)? {
+        let entry = entry?;
+        if is_storage_file(&entry.path()).is_ok() && entry.metadata()?.len() > 0 {
+            return Err(KvError::UnsupportedVersion(0));
         }
     }
+
+    write_file_atomically(
+        dir_path,
+        FORMAT_FILE_NAME,
+        FORMAT_VERSION.to_string().as_bytes(),
+    )
 }
 
 /// 从指定目录中读取已排序的`Storage`
-fn load_storages_sorted(dir_path: &Path) -> Result<Vec<Storage>> {
+fn load_storages_sorted(dir_path: &Path, io_type: IOType) -> Result<Vec<Storage>> {
     let mut storages = fs::read_dir(dir_path)?
         .flat_map(|entry| -> Result<_> { Ok(entry?.path()) })
-        .filter_map(|gen_path| Storage::new(gen_path.as_path()).ok())
+        .filter(|gen_path| gen_path.is_file())
+        .filter_map(|gen_path| Storage::new(gen_path.as_path(), io_type).ok())
         .collect::<Vec<Storage>>();
     storages.sort_by_key(|s| s.gen);
     Ok(storages)
 }
 
-/// 从`Storage`集合中构建索引
+/// 尚未读取到提交标记的批量写入记录：(类型, keyspace, key, 位置)
+type PendingRecord = (RecordType, u32, Vec<u8>, RecordPos);
+
+/// 从`Storage`集合中构建全部 keyspace 的索引，并返回已使用的最大批量写入序列号及活跃文件的hint
+///
+/// 旧文件存在有效的hint文件时直接从中加载，否则扫描并校验`Storage`后补写hint文件；
+/// 批量写入的`Record`只有在读取到对应的提交标记后才会被应用至索引；
+/// 同时统计可被合并回收的字节数。
+/// 持久化索引存在检查点时，只重放检查点之后的`Record`，检查点之前的旧文件无需读取
 fn build_index_from_storage(
-    storages: &mut Vec<Storage>,
-    index_type: IndexType,
-) -> Result<Box<dyn Index>> {
-    let index = new_index(index_type);
+    dir_path: &Path,
+    storages: &mut [Storage],
+    indexes: &IndexesById,
+    checkpoint: Option<Checkpoint>,
+    recovery_mode: RecoveryMode,
+) -> Result<(usize, Hint, u64)> {
+    let mut max_seq = checkpoint.map_or(NON_BATCH_SEQ, |c| c.seq);
+    let mut active_hint = Hint::default();
+    let mut reclaimable_size = checkpoint.map_or(0, |c| c.reclaimable_size);
     if storages.is_empty() {
-        return Ok(Box::new(index));
+        return Ok((max_seq, active_hint, reclaimable_size));
     }
 
+    // 暂存尚未读取到提交标记的批量写入记录
+    let mut pending_batches: HashMap<usize, Vec<PendingRecord>> = HashMap::new();
+
+    let active_gen = storages[storages.len() - 1].gen;
     for storage in storages.iter_mut() {
-        let mut offset = 0;
-        loop {
-            let record = match storage.read_record_head_buf(offset) {
-                Ok(r) => r,
-                Err(e) => {
-                    if let KvError::ReadEOF = e {
-                        break;
-                    }
-                    return Err(e);
+        let is_active = storage.gen == active_gen;
+        let hint_path = dir_path.join(hint_name_from_gen(storage.gen));
+        let data_size = fs::metadata(dir_path.join(storage_name_from_gen(storage.gen)))?.len();
+
+        // 活跃文件仍需读取，以恢复其hint并截断写入中断的数据
+        if !is_active && checkpoint.is_some_and(|c| storage.gen < c.gen) {
+            storage.set_offset(data_size);
+            continue;
+        }
+
+        // 活跃文件的hint文件在正常关闭时写入，与文件大小一致时同样有效
+        let (entries, offset) = match load_hint(&hint_path, data_size)? {
+            Some(entries) => (entries, data_size),
+            None => {
+                let (entries, offset) = scan_storage(storage, data_size, is_active, recovery_mode)?;
+                if !is_active {
+                    hint_from_entries(&entries).write_to(&hint_path, data_size)?;
                 }
-            };
-            let record_size = record.encoded_len();
+                (entries, offset)
+            }
+        };
+        if is_active {
+            active_hint = hint_from_entries(&entries);
+        }
 
-            // 构建索引
-            let key = storage.read_key_from_header(offset, &record)?;
+        for entry in entries {
             let record_mate = RecordPos {
                 gen: storage.gen,
-                offset,
+                offset: entry.offset,
+                size: entry.size,
+                expire_at: entry.expire_at,
             };
+            if checkpoint.is_some_and(|c| !c.needs_replay(&record_mate)) {
+                continue;
+            }
 
-            match record.record_type {
-                RecordType::UnexpectCommand => break,
-                RecordType::Normal => index.put(key, record_mate),
-                RecordType::Remove => index.delete(key.as_slice()),
-            };
-            offset += record_size as u64;
+            // 构建索引
+            let (key, seq) = parse_seq_key(entry.key)?;
+            if seq == NON_BATCH_SEQ {
+                reclaimable_size +=
+                    apply_to_index(indexes, entry.keyspace, entry.record_type, key, record_mate)?;
+            } else {
+                max_seq = max_seq.max(seq);
+                match entry.record_type {
+                    RecordType::BatchCommit => {
+                        // 提交标记已写入，应用该批次的全部记录
+                        reclaimable_size += record_mate.size as u64;
+                        for (record_type, keyspace, key, pos) in
+                            pending_batches.remove(&seq).unwrap_or_default()
+                        {
+                            reclaimable_size +=
+                                apply_to_index(indexes, keyspace, record_type, key, pos)?;
+                        }
+                    }
+                    record_type => pending_batches.entry(seq).or_default().push((
+                        record_type,
+                        entry.keyspace,
+                        key,
+                        record_mate,
+                    )),
+                }
+            }
         }
         // 设置数据偏移
         storage.set_offset(offset);
     }
 
-    Ok(Box::new(index))
+    // 未提交的批量写入记录
+    reclaimable_size += pending_batches
+        .values()
+        .flatten()
+        .map(|(_, _, _, pos)| pos.size as u64)
+        .sum::<u64>();
+
+    Ok((max_seq, active_hint, reclaimable_size))
+}
+
+/// 扫描并校验`Storage`中的全部`Record`，返回其索引信息及最后一条完整`Record`的末尾偏移
+///
+/// 活跃文件中损坏、且其后不存在有效`Record`的数据被视为写入中断，扫描在此停止；
+/// 其余损坏的`Record`按照`recovery_mode`处理，宽松模式下从其后首个有效的`Record`继续
+fn scan_storage(
+    storage: &Storage,
+    data_size: u64,
+    is_active: bool,
+    recovery_mode: RecoveryMode,
+) -> Result<(Vec<HintEntry>, u64)> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset < data_size {
+        let err = match storage.read_record_head_buf(offset) {
+            Ok(header) if offset + header.encoded_len() as u64 <= data_size => {
+                let record_size = header.encoded_len() as u64;
+                let entry = storage.read_record(offset).and_then(|r| {
+                    // 序列号无法解析的`Record`同样视为损坏
+                    decode_length_delimiter(r.key.as_slice())?;
+                    Ok(HintEntry {
+                        record_type: header.record_type,
+                        keyspace: r.keyspace,
+                        expire_at: r.expire_at(),
+                        key: r.key,
+                        offset,
+                        size: record_size as u32,
+                    })
+                });
+                match entry {
+                    Ok(entry) => {
+                        entries.push(entry);
+                        offset += record_size;
+                        continue;
+                    }
+                    Err(e) => e,
+                }
+            }
+            // 不完整的`Record`
+            Ok(_) => KvError::ReadEOF,
+            Err(e) => e,
+        };
+        if let KvError::Io(e) = err {
+            return Err(KvError::Io(e));
+        }
+
+        // 无法解析或校验失败的`Record`，只能从其后首个有效的`Record`继续
+        let next = storage.find_valid_record(offset + 1, data_size)?;
+        if is_active && next.is_none() {
+            break;
+        }
+        if recovery_mode == RecoveryMode::Strict {
+            return Err(err);
+        }
+        let next = next.unwrap_or(data_size);
+        tracing::warn!(
+            "skip {} corrupted bytes in storage {} at offset {}: {}",
+            next - offset,
+            storage.gen,
+            offset,
+            err
+        );
+        offset = next;
+    }
+    Ok((entries, offset))
+}
+
+#[inline]
+fn hint_from_entries(entries: &[HintEntry]) -> Hint {
+    let mut hint = Hint::default();
+    for entry in entries {
+        hint.push(
+            entry.record_type,
+            entry.keyspace,
+            &entry.key,
+            entry.offset,
+            entry.size,
+            entry.expire_at,
+        );
+    }
+    hint
+}
+
+/// 截断活跃文件中`offset`之后写入中断的数据，返回被丢弃的字节数
+fn truncate_torn_tail(gen_path: &Path, offset: u64) -> Result<u64> {
+    let fd = OpenOptions::new().write(true).open(gen_path)?;
+    let size = fd.metadata()?.len();
+    if size <= offset {
+        return Ok(0);
+    }
+
+    fd.set_len(offset)?;
+    fd.sync_all()?;
+    Ok(size - offset)
+}
+
+/// 全部索引共同的检查点，任一索引的检查点缺失或不一致时返回`None`
+fn common_checkpoint(indexes: &IndexesById) -> Result<Option<Checkpoint>> {
+    let mut common = None;
+    for index in indexes.values() {
+        let Some(checkpoint) = index.checkpoint()? else {
+            return Ok(None);
+        };
+        if common.is_some_and(|c| c != checkpoint) {
+            return Ok(None);
+        }
+        common = Some(checkpoint);
+    }
+    Ok(common)
+}
+
+/// 将`Record`应用至所属`keyspace`的索引，返回因此可被合并回收的字节数
+///
+/// 已过期的写入视为删除，其本身及被覆盖的`Record`均可被合并回收；
+/// 已删除或清空的 keyspace 的`Record`均可被合并回收
+#[inline]
+fn apply_to_index(
+    indexes: &IndexesById,
+    keyspace: u32,
+    record_type: RecordType,
+    key: Vec<u8>,
+    pos: RecordPos,
+) -> Result<u64> {
+    let Some(index) = indexes.get(&keyspace) else {
+        return Ok(pos.size as u64);
+    };
+    let old_pos = match record_type {
+        RecordType::Expiring | RecordType::MergeOperand if pos.is_expired() => {
+            return Ok(pos.size as u64 + index.delete(key.as_slice())?.map_or(0, |p| p.size as u64))
+        }
+        RecordType::Normal | RecordType::Expiring | RecordType::MergeOperand => {
+            index.put(key, pos)?
+        }
+        RecordType::Remove => {
+            return Ok(pos.size as u64 + index.delete(key.as_slice())?.map_or(0, |p| p.size as u64))
+        }
+        RecordType::UnexpectCommand | RecordType::BatchCommit => None,
+    };
+    Ok(old_pos.map_or(0, |p| p.size as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        config::IteratorConfig,
+        index::IndexType,
+        test_util::{config, open, remove_hints, storages_size},
+    };
+
+    fn active_path(dir: &TempDir) -> std::path::PathBuf {
+        dir.path().join(storage_name_from_gen(0))
+    }
+
+    /// 在活跃文件末尾追加`data`，模拟崩溃时写入中断的数据
+    fn append_to_active(dir: &TempDir, data: &[u8]) {
+        use std::io::Write;
+        let mut fd = OpenOptions::new()
+            .append(true)
+            .open(active_path(dir))
+            .unwrap();
+        fd.write_all(data).unwrap();
+    }
+
+    fn write_keys(engine: &Engine, range: std::ops::Range<usize>) {
+        for i in range {
+            engine
+                .set(format!("key-{}", i), format!("value-{}", i))
+                .unwrap();
+        }
+    }
+
+    fn assert_keys(engine: &Engine, range: std::ops::Range<usize>) {
+        for i in range {
+            assert_eq!(
+                engine.get(format!("key-{}", i)).unwrap(),
+                format!("value-{}", i)
+            );
+        }
+    }
+
+    #[test]
+    fn torn_tail_is_truncated_and_reported() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        write_keys(&engine, 0..10);
+        assert_eq!(engine.stat().unwrap().discarded_size, 0);
+        engine.close().unwrap();
+        let size = fs::metadata(active_path(&dir)).unwrap().len();
+
+        // 只写入了一半的`Record`
+        let record = Record::new_set(key_with_seq(b"torn", NON_BATCH_SEQ), vec![b'x'; 64]);
+        let data = record.encode().unwrap();
+        append_to_active(&dir, &data[..data.len() / 2]);
+
+        let engine = open(&dir);
+        assert_eq!(engine.stat().unwrap().discarded_size, data.len() as u64 / 2);
+        assert_eq!(fs::metadata(active_path(&dir)).unwrap().len(), size);
+        assert_keys(&engine, 0..10);
+        assert!(matches!(engine.get("torn"), Err(KvError::InvalidKey)));
+
+        // 截断后的写入位于完整数据之后
+        write_keys(&engine, 10..20);
+        engine.close().unwrap();
+        let engine = open(&dir);
+        assert_eq!(engine.stat().unwrap().discarded_size, 0);
+        assert_keys(&engine, 0..20);
+    }
+
+    /// 写入10条长度相同的`Record`后关闭，删除活跃文件的hint以扫描校验，返回每条`Record`的长度
+    fn write_fixed_records(dir: &TempDir) -> u64 {
+        let engine = open(dir);
+        write_keys(&engine, 0..10);
+        engine.close().unwrap();
+        fs::remove_file(dir.path().join(hint_name_from_gen(0))).unwrap();
+
+        let record = Record::new_set(key_with_seq(b"key-0", NON_BATCH_SEQ), b"value-0".to_vec());
+        record.encode().unwrap().len() as u64
+    }
+
+    /// 修改活跃文件中`offset`处的一个字节
+    fn corrupt_active(dir: &TempDir, offset: u64, byte: u8) {
+        let mut data = fs::read(active_path(dir)).unwrap();
+        data[offset as usize] = byte;
+        fs::write(active_path(dir), data).unwrap();
+    }
+
+    fn open_lenient(dir: &TempDir) -> Engine {
+        Engine::new(Config {
+            recovery_mode: RecoveryMode::Lenient,
+            ..config(dir)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn torn_tail_with_invalid_crc_is_truncated() {
+        let dir = TempDir::new().unwrap();
+        let record_len = write_fixed_records(&dir);
+        // 最后一条`Record`的crc
+        corrupt_active(&dir, record_len * 10 - 1, 0);
+
+        let engine = open(&dir);
+        assert_eq!(engine.stat().unwrap().discarded_size, record_len);
+        assert_keys(&engine, 0..9);
+        assert!(matches!(engine.get("key-9"), Err(KvError::InvalidKey)));
+    }
+
+    #[test]
+    fn undecodable_header_in_middle_of_active_fails_in_strict_mode() {
+        let dir = TempDir::new().unwrap();
+        let record_len = write_fixed_records(&dir);
+        // 第3条`Record`的 type
+        corrupt_active(&dir, record_len * 2, 0);
+
+        assert!(Engine::new(config(&dir)).is_err());
+        assert_eq!(
+            fs::metadata(active_path(&dir)).unwrap().len(),
+            record_len * 10
+        );
+
+        // 宽松模式跳过损坏的`Record`，其后的数据不被截断
+        let engine = open_lenient(&dir);
+        assert_eq!(engine.stat().unwrap().discarded_size, 0);
+        assert_keys(&engine, 0..2);
+        assert!(matches!(engine.get("key-2"), Err(KvError::InvalidKey)));
+        assert_keys(&engine, 3..10);
+    }
+
+    #[test]
+    fn oversized_record_in_middle_of_active_fails_in_strict_mode() {
+        let dir = TempDir::new().unwrap();
+        let record_len = write_fixed_records(&dir);
+        // 第9条`Record`的 key size，其末尾超出文件大小
+        corrupt_active(&dir, record_len * 8 + 1, 0x7f);
+
+        assert!(Engine::new(config(&dir)).is_err());
+
+        let engine = open_lenient(&dir);
+        assert_eq!(engine.stat().unwrap().discarded_size, 0);
+        assert_keys(&engine, 0..8);
+        assert!(matches!(engine.get("key-8"), Err(KvError::InvalidKey)));
+        assert_keys(&engine, 9..10);
+    }
+
+    #[test]
+    fn invalid_crc_in_middle_of_active_fails_in_strict_mode() {
+        let dir = TempDir::new().unwrap();
+        let record_len = write_fixed_records(&dir);
+        corrupt_active(&dir, record_len * 5 - 1, 0);
+
+        assert!(matches!(
+            Engine::new(config(&dir)),
+            Err(KvError::InvalidCrc)
+        ));
+        let engine = open_lenient(&dir);
+        assert_keys(&engine, 0..4);
+        assert!(matches!(engine.get("key-4"), Err(KvError::InvalidKey)));
+        assert_keys(&engine, 5..10);
+    }
+
+    #[test]
+    fn undecodable_key_in_middle_of_active_is_skipped_in_lenient_mode() {
+        let dir = TempDir::new().unwrap();
+        let record_len = write_fixed_records(&dir);
+        // crc正确但序列号无法解析的`Record`
+        let bad = Record::new_set(vec![0xff; 11], b"value".to_vec())
+            .encode()
+            .unwrap();
+        let mut data = fs::read(active_path(&dir)).unwrap();
+        let at = (record_len * 5) as usize;
+        data.splice(at..at, bad);
+        fs::write(active_path(&dir), data).unwrap();
+
+        assert!(matches!(
+            Engine::new(config(&dir)),
+            Err(KvError::DecodeError(_))
+        ));
+        let engine = open_lenient(&dir);
+        assert_eq!(engine.stat().unwrap().discarded_size, 0);
+        assert_keys(&engine, 0..10);
+    }
+
+    #[test]
+    fn large_record_after_corruption_is_found() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        write_keys(&engine, 0..3);
+        // 大于单次查找读取的字节数
+        let big = vec![b'x'; 100 * 1024];
+        engine.set(b"big".to_vec(), big.clone()).unwrap();
+        write_keys(&engine, 3..5);
+        engine.close().unwrap();
+        fs::remove_file(dir.path().join(hint_name_from_gen(0))).unwrap();
+
+        let record_len = Record::new_set(key_with_seq(b"key-0", NON_BATCH_SEQ), b"value-0".to_vec())
+            .encode()
+            .unwrap()
+            .len() as u64;
+        corrupt_active(&dir, record_len * 2, 0);
+
+        assert!(Engine::new(config(&dir)).is_err());
+        let engine = open_lenient(&dir);
+        assert_keys(&engine, 0..2);
+        assert!(matches!(engine.get("key-2"), Err(KvError::InvalidKey)));
+        assert_eq!(engine.get("big").unwrap(), big);
+        assert_keys(&engine, 3..5);
+    }
+
+    #[test]
+    fn memory_map_reads_older_storages_across_reopen_and_merge() {
+        let dir = TempDir::new().unwrap();
+        let open = || {
+            Engine::new(Config {
+                storage_size: 1024,
+                io_type: IOType::MemoryMap,
+                ..config(&dir)
+            })
+            .unwrap()
+        };
+        let assert_data = |engine: &Engine| {
+            assert_keys(engine, 0..100);
+            for i in 100..200 {
+                assert!(matches!(
+                    engine.get(format!("key-{}", i)),
+                    Err(KvError::InvalidKey)
+                ));
+            }
+            assert_eq!(engine.list_keys("key-").unwrap().len(), 100);
+            let mut count = 0;
+            engine
+                .fold(|_, _| {
+                    count += 1;
+                    true
+                })
+                .unwrap();
+            assert_eq!(count, 100);
+        };
+
+        let engine = open();
+        write_keys(&engine, 0..200);
+        for i in 100..200 {
+            engine.delete(format!("key-{}", i)).unwrap();
+        }
+        // 旧`Storage`以内存映射读取
+        assert!(engine.stat().unwrap().storage_num > 1);
+        assert_data(&engine);
+        engine.close().unwrap();
+
+        let engine = open();
+        assert_data(&engine);
+        engine.merge().unwrap();
+        assert_data(&engine);
+        // 合并后写入的数据及新的旧`Storage`同样可读
+        write_keys(&engine, 0..100);
+        engine.close().unwrap();
+
+        let engine = open();
+        assert_data(&engine);
+    }
+
+    #[test]
+    fn close_writes_active_hint_and_allows_reopen() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        write_keys(&engine, 0..10);
+        engine.delete("key-0").unwrap();
+        engine.close().unwrap();
+
+        // 活跃文件的hint与文件大小一致，重启时无需扫描
+        let hint_path = dir.path().join(hint_name_from_gen(0));
+        let data_size = fs::metadata(active_path(&dir)).unwrap().len();
+        let entries = load_hint(&hint_path, data_size).unwrap().unwrap();
+        assert_eq!(entries.len(), 11);
+
+        let engine = open(&dir);
+        assert_eq!(engine.stat().unwrap().discarded_size, 0);
+        assert!(matches!(engine.get("key-0"), Err(KvError::InvalidKey)));
+        assert_keys(&engine, 1..10);
+        write_keys(&engine, 10..20);
+        engine.close().unwrap();
+
+        let engine = open(&dir);
+        assert_keys(&engine, 1..20);
+        assert_eq!(engine.stat().unwrap().key_num, 19);
+    }
+
+    #[test]
+    fn data_directory_is_locked_while_open() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        assert!(matches!(
+            Engine::new(config(&dir)),
+            Err(KvError::DirectoryLocked)
+        ));
+
+        // 关闭或释放后可以再次打开
+        engine.close().unwrap();
+        let engine = open(&dir);
+        assert!(matches!(
+            Engine::new(config(&dir)),
+            Err(KvError::DirectoryLocked)
+        ));
+        drop(engine);
+        drop(open(&dir));
+    }
+
+    #[test]
+    fn new_directory_records_format_version() {
+        let dir = TempDir::new().unwrap();
+        open(&dir).close().unwrap();
+        let version = fs::read_to_string(dir.path().join(FORMAT_FILE_NAME)).unwrap();
+        assert_eq!(version, FORMAT_VERSION.to_string());
+        open(&dir);
+    }
+
+    #[test]
+    fn unversioned_data_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        // 引入版本之前写入的数据，key 中不包含序列号
+        let record = Record::new_set(b"key".to_vec(), b"value".to_vec());
+        fs::write(
+            dir.path().join(storage_name_from_gen(0)),
+            record.encode().unwrap(),
+        )
+        .unwrap();
+        assert!(matches!(
+            Engine::new(config(&dir)),
+            Err(KvError::UnsupportedVersion(0))
+        ));
+        assert!(!dir.path().join(FORMAT_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn unknown_format_version_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(FORMAT_FILE_NAME), "2").unwrap();
+        assert!(matches!(
+            Engine::new(config(&dir)),
+            Err(KvError::UnsupportedVersion(2))
+        ));
+
+        fs::write(dir.path().join(FORMAT_FILE_NAME), "garbage").unwrap();
+        assert!(matches!(
+            Engine::new(config(&dir)),
+            Err(KvError::CorruptedFile(path)) if path.ends_with(FORMAT_FILE_NAME)
+        ));
+    }
+
+    #[test]
+    fn compare_and_swap_matches_current_value() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+
+        // 不存在的 key 仅与`None`匹配
+        assert!(!engine
+            .compare_and_swap("k", Some(b"v0".as_slice()), Some(b"v1".to_vec()))
+            .unwrap());
+        assert!(matches!(engine.get("k"), Err(KvError::InvalidKey)));
+        assert!(engine
+            .compare_and_swap("k", None, Some(b"v1".to_vec()))
+            .unwrap());
+        assert_eq!(engine.get("k").unwrap(), "v1");
+        assert!(!engine.set_if_absent("k", "v2").unwrap());
+
+        // value 不一致时保持不变
+        assert!(!engine
+            .compare_and_swap("k", Some(b"v0".as_slice()), Some(b"v2".to_vec()))
+            .unwrap());
+        assert!(!engine
+            .compare_and_swap("k", None, Some(b"v2".to_vec()))
+            .unwrap());
+        assert_eq!(engine.get("k").unwrap(), "v1");
+        assert!(engine
+            .compare_and_swap("k", Some(b"v1".as_slice()), Some(b"v2".to_vec()))
+            .unwrap());
+        assert_eq!(engine.get("k").unwrap(), "v2");
+
+        // `new`为`None`时删除 key
+        assert!(engine
+            .compare_and_swap("k", Some(b"v2".as_slice()), None)
+            .unwrap());
+        assert!(matches!(engine.get("k"), Err(KvError::InvalidKey)));
+        assert!(engine.compare_and_swap("k", None, None).unwrap());
+        assert!(matches!(engine.get("k"), Err(KvError::InvalidKey)));
+
+        assert!(matches!(
+            engine.compare_and_swap("", None, Some(b"v".to_vec())),
+            Err(KvError::InvalidKey)
+        ));
+        engine.close().unwrap();
+
+        let engine = open(&dir);
+        assert!(matches!(engine.get("k"), Err(KvError::InvalidKey)));
+    }
+
+    #[test]
+    fn compare_and_swap_treats_expired_key_as_absent() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine
+            .set_with_ttl("k", "old", Duration::from_millis(50))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert!(!engine
+            .compare_and_swap("k", Some(b"old".as_slice()), Some(b"new".to_vec()))
+            .unwrap());
+        assert!(engine.set_if_absent("k", "new").unwrap());
+        assert_eq!(engine.get("k").unwrap(), "new");
+        assert_eq!(engine.ttl("k").unwrap(), None);
+    }
+
+    #[test]
+    fn concurrent_compare_and_swap_has_one_winner() {
+        const THREADS: usize = 8;
+        const ROUNDS: usize = 50;
+
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine.set("counter", "0").unwrap();
+
+        // 每轮全部线程以相同的旧值竞争，只有一个线程成功
+        for round in 0..ROUNDS {
+            let expected = round.to_string();
+            let wins = std::thread::scope(|s| {
+                let handles = (0..THREADS)
+                    .map(|_| {
+                        s.spawn(|| {
+                            engine
+                                .compare_and_swap(
+                                    "counter",
+                                    Some(expected.as_bytes()),
+                                    Some((round + 1).to_string().into_bytes()),
+                                )
+                                .unwrap()
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().unwrap())
+                    .filter(|won| *won)
+                    .count()
+            });
+            assert_eq!(wins, 1);
+        }
+        assert_eq!(engine.get("counter").unwrap(), ROUNDS.to_string());
+    }
+
+    /// 写入即将过期、长期有效及永不过期的 key，等待前者过期
+    fn write_expiring(engine: &Engine) {
+        for i in 0..20 {
+            let key = format!("short-{:02}", i);
+            engine
+                .set_with_ttl(key.clone(), key, Duration::from_millis(100))
+                .unwrap();
+            let key = format!("long-{:02}", i);
+            engine
+                .set_with_ttl(key.clone(), key, Duration::from_secs(3600))
+                .unwrap();
+            let key = format!("forever-{:02}", i);
+            engine.set(key.clone(), key).unwrap();
+        }
+        // 被永不过期的写入覆盖的 key 不再过期
+        engine
+            .set_with_ttl("renewed", "old", Duration::from_millis(100))
+            .unwrap();
+        engine.set("renewed", "new").unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+    }
+
+    fn assert_expired(engine: &Engine) {
+        for i in 0..20 {
+            let key = format!("short-{:02}", i);
+            assert!(matches!(engine.get(key.clone()), Err(KvError::InvalidKey)));
+            assert!(matches!(engine.ttl(key), Err(KvError::InvalidKey)));
+
+            let key = format!("long-{:02}", i);
+            assert_eq!(engine.get(key.clone()).unwrap(), key);
+            let ttl = engine.ttl(key).unwrap().unwrap();
+            assert!(ttl > Duration::from_secs(3500) && ttl <= Duration::from_secs(3600));
+
+            let key = format!("forever-{:02}", i);
+            assert_eq!(engine.get(key.clone()).unwrap(), key);
+            assert_eq!(engine.ttl(key).unwrap(), None);
+        }
+        assert_eq!(engine.get("renewed").unwrap(), "new");
+        assert_eq!(engine.ttl("renewed").unwrap(), None);
+
+        assert!(engine.list_keys("short-").unwrap().is_empty());
+        assert_eq!(engine.list_keys("long-").unwrap().len(), 20);
+        let mut iter = engine.iter(IteratorConfig::default()).unwrap();
+        let mut keys = 0;
+        while let Some(key) = iter.next_key() {
+            assert!(!key.unwrap().starts_with(b"short-"));
+            keys += 1;
+        }
+        assert_eq!(keys, 41);
+    }
+
+    #[test]
+    fn expired_keys_stay_absent_across_merge_and_reopen() {
+        for index_type in [IndexType::BTree, IndexType::BPlusTree] {
+            let dir = TempDir::new().unwrap();
+            let open = || {
+                Engine::new(Config {
+                    storage_size: 1024,
+                    index_type,
+                    ..config(&dir)
+                })
+                .unwrap()
+            };
+
+            let engine = open();
+            write_expiring(&engine);
+            assert_expired(&engine);
+            engine.close().unwrap();
+
+            // 重启时过期的`Record`视为删除
+            let engine = open();
+            assert_expired(&engine);
+            assert!(engine.stat().unwrap().reclaimable_size > 0);
+
+            // 合并时不再重写过期的`Record`
+            engine.merge().unwrap();
+            assert_expired(&engine);
+            assert_eq!(engine.stat().unwrap().key_num, 41);
+            engine.close().unwrap();
+
+            // 删除hint文件，从`Storage`重新构建索引
+            remove_hints(&dir);
+            let engine = open();
+            assert_expired(&engine);
+            assert_eq!(engine.stat().unwrap().key_num, 41);
+        }
+    }
+
+    /// 将操作数作为整数累加至原有的 value
+    fn counter_config(dir: &TempDir, index_type: IndexType) -> Config {
+        let add = |_: &[u8], existing: Option<&[u8]>, operand: &[u8]| -> Vec<u8> {
+            let parse = |v: &[u8]| std::str::from_utf8(v).unwrap().parse::<i64>().unwrap();
+            (existing.map_or(0, parse) + parse(operand))
+                .to_string()
+                .into_bytes()
+        };
+        Config {
+            storage_size: 1024,
+            index_type,
+            merge_operator: Some(Arc::new(add)),
+            ..config(dir)
+        }
+    }
+
+    #[test]
+    fn merge_value_requires_operator() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        assert!(matches!(
+            engine.merge_value("counter", "1"),
+            Err(KvError::MergeOperatorMissing)
+        ));
+    }
+
+    #[test]
+    fn operands_are_folded_onto_the_base_value() {
+        let dir = TempDir::new().unwrap();
+        let engine = Engine::new(counter_config(&dir, IndexType::BTree)).unwrap();
+
+        // 不存在的 key 从空值开始合并
+        engine.merge_value("a", "1").unwrap();
+        engine.merge_value("a", "2").unwrap();
+        assert_eq!(engine.get("a").unwrap(), "3");
+
+        engine.set("b", "10").unwrap();
+        engine.merge_value("b", "5").unwrap();
+        let snapshot = engine.snapshot().unwrap();
+        engine.merge_value("b", "-3").unwrap();
+        assert_eq!(engine.get("b").unwrap(), "12");
+        assert_eq!(snapshot.get("b").unwrap(), "15");
+        drop(snapshot);
+
+        // 删除后重新从空值开始，覆盖写入后从新的 value 开始
+        engine.delete("a").unwrap();
+        engine.merge_value("a", "7").unwrap();
+        assert_eq!(engine.get("a").unwrap(), "7");
+        engine.set("b", "100").unwrap();
+        engine.merge_value("b", "1").unwrap();
+        assert_eq!(engine.get("b").unwrap(), "101");
+
+        // 操作数保留原有 value 的过期时间
+        engine
+            .set_with_ttl("c", "1", Duration::from_secs(3600))
+            .unwrap();
+        engine.merge_value("c", "1").unwrap();
+        assert_eq!(engine.get("c").unwrap(), "2");
+        assert!(engine.ttl("c").unwrap().is_some());
+    }
+
+    #[test]
+    fn operands_survive_merge_and_reopen() {
+        for index_type in [IndexType::BTree, IndexType::BPlusTree] {
+            let dir = TempDir::new().unwrap();
+            let engine = Engine::new(counter_config(&dir, index_type)).unwrap();
+            // 操作数跨越多个`Storage`
+            for i in 0..10 {
+                engine
+                    .set(format!("counter-{}", i), "0".to_string())
+                    .unwrap();
+            }
+            for _ in 0..20 {
+                for i in 0..10 {
+                    engine
+                        .merge_value(format!("counter-{}", i), i.to_string())
+                        .unwrap();
+                }
+            }
+            let assert_counters = |engine: &Engine| {
+                for i in 0..10 {
+                    let value = engine.get(format!("counter-{}", i)).unwrap();
+                    assert_eq!(value, (i * 20).to_string());
+                }
+            };
+            assert_counters(&engine);
+            engine.close().unwrap();
+
+            let engine = Engine::new(counter_config(&dir, index_type)).unwrap();
+            assert_counters(&engine);
+
+            // 合并将操作数合并为完整的 value，不再需要读取之前的`Record`
+            let size_before = storages_size(&dir);
+            engine.merge().unwrap();
+            assert_counters(&engine);
+            assert!(storages_size(&dir) < size_before / 4);
+
+            // 合并后的操作数写入新的 value 之上
+            engine.merge_value("counter-1", "1").unwrap();
+            assert_eq!(engine.get("counter-1").unwrap(), "21");
+            engine.close().unwrap();
+
+            let engine = Engine::new(counter_config(&dir, index_type)).unwrap();
+            assert_eq!(engine.get("counter-1").unwrap(), "21");
+            assert_eq!(engine.get("counter-2").unwrap(), "40");
+        }
+    }
 }
@@ -1,5 +1,5 @@
 use prost::{DecodeError, EncodeError};
-use std::io;
+use std::{io, path::PathBuf};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -27,6 +27,42 @@ pub enum KvError {
 
     #[error("invalid crc")]
     InvalidCrc,
+
+    #[error("write to read-only io")]
+    ReadOnlyIO,
+
+    #[error("exceed max batch num")]
+    ExceedMaxBatchNum,
+
+    #[error("unsupported data format version: {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("corrupted file: {}", .0.display())]
+    CorruptedFile(PathBuf),
+
+    #[error("data directory is already in use")]
+    DirectoryLocked,
+
+    #[error("merge is in progress")]
+    MergeInProgress,
+
+    #[error("merge output exceeds reserved generations")]
+    MergeGenExhausted,
+
+    #[error("merge operator is not configured")]
+    MergeOperatorMissing,
+
+    #[error("invalid keyspace name")]
+    InvalidKeyspaceName,
+
+    #[error("keyspace not found")]
+    KeyspaceNotFound,
+
+    #[error("transaction conflicts with a concurrent write")]
+    TransactionConflict,
+
+    #[error("index error: {0}")]
+    IndexError(Box<dyn std::error::Error + Send + Sync>),
 }
 
 /// Result type for kvs.
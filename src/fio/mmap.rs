@@ -0,0 +1,44 @@
+use std::{fs::OpenOptions, path::Path};
+
+use memmap2::Mmap;
+
+use crate::error::{KvError, Result};
+
+use super::FileIO;
+
+/// 基于内存映射的只读文件IO，用于不再写入的旧`Storage`
+pub(crate) struct MmapIO {
+    map: Mmap,
+}
+
+impl MmapIO {
+    pub(crate) fn new(file_path: &Path) -> Result<Self> {
+        let fd = OpenOptions::new().read(true).open(file_path)?;
+        // 旧`Storage`只读且不会被截断
+        let map = unsafe { Mmap::map(&fd)? };
+        Ok(Self { map })
+    }
+}
+
+impl FileIO for MmapIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let len = self.map.len() as u64;
+        if offset >= len {
+            return Err(KvError::ReadEOF);
+        }
+
+        let start = offset as usize;
+        let end = (start + buf.len()).min(self.map.len());
+        let read_len = end - start;
+        buf[..read_len].copy_from_slice(&self.map[start..end]);
+        Ok(read_len)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize> {
+        Err(KvError::ReadOnlyIO)
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
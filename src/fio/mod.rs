@@ -1,3 +1,4 @@
+mod mmap;
 mod stdio;
 
 use std::path::Path;
@@ -12,6 +13,18 @@ pub(crate) trait FileIO: Sync + Send {
     fn sync(&self) -> Result<()>;
 }
 
-pub(crate) fn new_file_io(file_path: &Path) -> Result<impl FileIO> {
-    stdio::StdIO::new(file_path)
+/// 文件IO类型，活跃文件始终使用标准文件IO
+#[derive(Clone, Copy)]
+pub enum IOType {
+    /// 标准文件IO
+    StandardFIO,
+    /// 内存映射，仅用于只读的旧`Storage`
+    MemoryMap,
+}
+
+pub(crate) fn new_file_io(file_path: &Path, io_type: IOType) -> Result<Box<dyn FileIO>> {
+    match io_type {
+        IOType::StandardFIO => Ok(Box::new(stdio::StdIO::new(file_path)?)),
+        IOType::MemoryMap => Ok(Box::new(mmap::MmapIO::new(file_path)?)),
+    }
 }
@@ -1,6 +1,6 @@
 use std::{
     fs::{File, OpenOptions},
-    io::Write,
+    io::{self, Write},
     path::Path,
     sync::Arc,
 };
@@ -20,7 +20,6 @@ impl StdIO {
         let fd = OpenOptions::new()
             .create(true)
             .read(true)
-            .write(true)
             .append(true)
             .open(file_path)?;
         Ok(Self {
@@ -36,6 +35,31 @@ impl FileIO for StdIO {
         self.fd.read().seek_read(buf, offset).map_err(KvError::Io)
     }
 
+    /// 按位置读取数据直至填满buf或读取至文件末尾
+    ///
+    /// 未读取到任何数据时返回`KvError::ReadEOF`
+    #[cfg(unix)]
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        use std::os::unix::prelude::FileExt;
+        let fd = self.fd.read();
+
+        let mut read_len = 0;
+        while read_len < buf.len() {
+            match fd.read_at(&mut buf[read_len..], offset + read_len as u64) {
+                // 已读取至文件末尾
+                Ok(0) => break,
+                Ok(n) => read_len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(KvError::Io(e)),
+            }
+        }
+
+        if read_len == 0 && !buf.is_empty() {
+            return Err(KvError::ReadEOF);
+        }
+        Ok(read_len)
+    }
+
     fn write(&self, buf: &[u8]) -> Result<usize> {
         self.fd.write().write(buf).map_err(KvError::Io)
     }
@@ -44,3 +68,55 @@ impl FileIO for StdIO {
         self.fd.write().sync_all().map_err(KvError::Io)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn open_with(dir: &TempDir, data: &[u8]) -> StdIO {
+        let io = StdIO::new(&dir.path().join("0.storage")).unwrap();
+        assert_eq!(io.write(data).unwrap(), data.len());
+        io
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_stops_at_end_of_file() {
+        let dir = TempDir::new().unwrap();
+        let io = open_with(&dir, b"hello world");
+
+        let mut buf = [0; 5];
+        assert_eq!(io.read(&mut buf, 6).unwrap(), 5);
+        assert_eq!(&buf, b"world");
+
+        // 跨越文件末尾时只读取剩余的部分
+        let mut buf = [0; 8];
+        assert_eq!(io.read(&mut buf, 8).unwrap(), 3);
+        assert_eq!(&buf[..3], b"rld");
+
+        let mut buf = [0; 4];
+        assert!(matches!(io.read(&mut buf, 11), Err(KvError::ReadEOF)));
+        assert!(matches!(io.read(&mut buf, 100), Err(KvError::ReadEOF)));
+        assert_eq!(io.read(&mut [], 11).unwrap(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_fills_large_buffer() {
+        let dir = TempDir::new().unwrap();
+        let data = (0..4 * 1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        let io = open_with(&dir, &data);
+
+        let mut buf = vec![0; data.len()];
+        assert_eq!(io.read(&mut buf, 0).unwrap(), data.len());
+        assert_eq!(buf, data);
+
+        // 追加写入后可以读取到新的数据
+        io.write(b"tail").unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(io.read(&mut buf, data.len() as u64 - 4).unwrap(), 8);
+        assert_eq!(&buf[4..], b"tail");
+    }
+}
@@ -0,0 +1,774 @@
+use std::{mem, ops::Bound, sync::Arc};
+
+use parking_lot::RwLock;
+
+use crate::{data::record::RecordPos, error::Result};
+
+use super::{
+    view::{range_contains, CursorSnapshot, KeyRange, RangeSeek, Views},
+    Index, IndexSnapshot,
+};
+
+/// 自适应基数树索引，共享前缀的 key 只存储一次
+pub(crate) struct AdaptiveRadixTree {
+    tree: Arc<RwLock<Art>>,
+    views: Views,
+}
+
+impl AdaptiveRadixTree {
+    pub(crate) fn new() -> Self {
+        Self {
+            tree: Arc::new(RwLock::new(Art::default())),
+            views: Views::default(),
+        }
+    }
+}
+
+impl Index for AdaptiveRadixTree {
+    fn put(&self, key: Vec<u8>, value: RecordPos) -> Result<Option<RecordPos>> {
+        let mut guard = self.tree.write();
+        self.views.record(&key, || guard.get(&key).copied());
+        Ok(guard.insert(&key, value))
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<RecordPos>> {
+        Ok(self.tree.read().get(key).copied())
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<Option<RecordPos>> {
+        let mut guard = self.tree.write();
+        self.views.record(key, || guard.get(key).copied());
+        Ok(guard.remove(key))
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.tree.read().len)
+    }
+
+    fn compare_and_put(&self, key: &[u8], expected: RecordPos, value: RecordPos) -> Result<bool> {
+        let mut guard = self.tree.write();
+        match guard.get_mut(key) {
+            Some(pos) if *pos == expected => {
+                self.views.record(key, || Some(expected));
+                *pos = value;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn clear(&self) -> Result<()> {
+        let mut guard = self.tree.write();
+        if !self.views.is_empty() {
+            let mut path = Vec::new();
+            let range = (Bound::Unbounded, Bound::Unbounded);
+            // 借助`accept`遍历全部 key
+            guard.root.seek(&mut path, range, false, &mut |key| {
+                if let Some(pos) = guard.get(key) {
+                    self.views.record(key, || Some(*pos));
+                }
+                false
+            });
+        }
+        *guard = Art::default();
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn IndexSnapshot>> {
+        let undo = {
+            let _guard = self.tree.read();
+            self.views.register()
+        };
+        Ok(Box::new(CursorSnapshot::new(self.tree.clone(), undo)))
+    }
+}
+
+impl RangeSeek for RwLock<Art> {
+    fn seek_in(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        accept: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Option<(Vec<u8>, RecordPos)> {
+        let mut path = Vec::new();
+        self.read().root.seek(&mut path, range, reverse, accept)
+    }
+}
+
+#[derive(Default)]
+struct Art {
+    root: Node,
+    len: usize,
+}
+
+/// 路径压缩后的节点，`prefix`为父节点分支字节之后的公共前缀
+#[derive(Default)]
+struct Node {
+    prefix: Box<[u8]>,
+    value: Option<RecordPos>,
+    children: Children,
+}
+
+/// 根据子节点数量自适应的子节点集合
+enum Children {
+    /// 最多4个子节点，按分支字节有序存储
+    Node4 { keys: Vec<u8>, nodes: Vec<Node> },
+    /// 最多16个子节点，按分支字节有序存储
+    Node16 { keys: Vec<u8>, nodes: Vec<Node> },
+    /// 最多48个子节点，`index`中存储子节点下标加一
+    Node48 {
+        index: Box<[u8; 256]>,
+        nodes: Vec<Node>,
+    },
+    /// 以分支字节直接寻址
+    Node256 {
+        nodes: Box<[Option<Node>; 256]>,
+        len: usize,
+    },
+}
+
+impl Default for Children {
+    fn default() -> Self {
+        Self::Node4 {
+            keys: Vec::new(),
+            nodes: Vec::new(),
+        }
+    }
+}
+
+impl Art {
+    fn get(&self, key: &[u8]) -> Option<&RecordPos> {
+        let mut node = &self.root;
+        let mut rest = key;
+        loop {
+            rest = rest.strip_prefix(&node.prefix[..])?;
+            let Some((&edge, tail)) = rest.split_first() else {
+                return node.value.as_ref();
+            };
+            node = node.children.get(edge)?;
+            rest = tail;
+        }
+    }
+
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut RecordPos> {
+        let mut node = &mut self.root;
+        let mut rest = key;
+        loop {
+            rest = rest.strip_prefix(&node.prefix[..])?;
+            let Some((&edge, tail)) = rest.split_first() else {
+                return node.value.as_mut();
+            };
+            node = node.children.get_mut(edge)?;
+            rest = tail;
+        }
+    }
+
+    fn insert(&mut self, key: &[u8], value: RecordPos) -> Option<RecordPos> {
+        let old = self.root.insert(key, value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<RecordPos> {
+        let old = self.root.remove(key);
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+}
+
+impl Node {
+    fn leaf(prefix: &[u8], value: RecordPos) -> Self {
+        Self {
+            prefix: prefix.into(),
+            value: Some(value),
+            children: Children::default(),
+        }
+    }
+
+    fn insert(&mut self, key: &[u8], value: RecordPos) -> Option<RecordPos> {
+        let common = common_prefix_len(&self.prefix, key);
+        if common < self.prefix.len() {
+            // 分裂公共前缀
+            let mut old = mem::take(self);
+            self.prefix = old.prefix[..common].into();
+            let edge = old.prefix[common];
+            old.prefix = old.prefix[common + 1..].into();
+            self.children.insert(edge, old);
+        }
+
+        let rest = &key[common..];
+        let Some((&edge, tail)) = rest.split_first() else {
+            return self.value.replace(value);
+        };
+        match self.children.get_mut(edge) {
+            Some(child) => child.insert(tail, value),
+            None => {
+                self.children.insert(edge, Node::leaf(tail, value));
+                None
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<RecordPos> {
+        let rest = key.strip_prefix(&self.prefix[..])?;
+        let Some((&edge, tail)) = rest.split_first() else {
+            return self.value.take();
+        };
+
+        let child = self.children.get_mut(edge)?;
+        let old = child.remove(tail)?;
+        if child.value.is_none() {
+            match child.children.len() {
+                0 => {
+                    self.children.remove(edge);
+                }
+                1 => child.merge_only_child(),
+                _ => {}
+            }
+        }
+        Some(old)
+    }
+
+    /// 将唯一的子节点合并至当前节点，保持路径压缩
+    fn merge_only_child(&mut self) {
+        let Some((edge, child)) = self.children.pop_only() else {
+            return;
+        };
+        let mut prefix = Vec::with_capacity(self.prefix.len() + 1 + child.prefix.len());
+        prefix.extend_from_slice(&self.prefix);
+        prefix.push(edge);
+        prefix.extend_from_slice(&child.prefix);
+
+        *self = child;
+        self.prefix = prefix.into();
+    }
+
+    /// 按 key 的顺序查找当前子树在`range`内首个（`reverse`时为最后一个）满足`accept`的 key，
+    /// `path`为当前节点之前的 key
+    fn seek(
+        &self,
+        path: &mut Vec<u8>,
+        range: KeyRange,
+        reverse: bool,
+        accept: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Option<(Vec<u8>, RecordPos)> {
+        let len = path.len();
+        path.extend_from_slice(&self.prefix);
+        let found = self.seek_subtree(path, range, reverse, accept);
+        path.truncate(len);
+        found
+    }
+
+    /// `path`为包含当前节点前缀的 key
+    fn seek_subtree(
+        &self,
+        path: &mut Vec<u8>,
+        range: KeyRange,
+        reverse: bool,
+        accept: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Option<(Vec<u8>, RecordPos)> {
+        // 子树中的 key 均以`path`为前缀，整棵子树均在范围之外时跳过
+        if !subtree_may_overlap(path, range) {
+            return None;
+        }
+
+        // 节点自身的 key 小于子树中的其余 key
+        if !reverse {
+            if let Some(found) = self.own_value(path, range, accept) {
+                return Some(found);
+            }
+        }
+        let children = self.children.iter();
+        let children: Box<dyn Iterator<Item = (u8, &Node)>> = if reverse {
+            Box::new(children.rev())
+        } else {
+            children
+        };
+        for (edge, child) in children {
+            path.push(edge);
+            let found = child.seek(path, range, reverse, accept);
+            path.pop();
+            if found.is_some() {
+                return found;
+            }
+        }
+        if reverse {
+            return self.own_value(path, range, accept);
+        }
+        None
+    }
+
+    fn own_value(
+        &self,
+        path: &[u8],
+        range: KeyRange,
+        accept: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Option<(Vec<u8>, RecordPos)> {
+        let value = self.value?;
+        (range_contains(range, path) && accept(path)).then(|| (path.to_vec(), value))
+    }
+}
+
+impl Children {
+    fn len(&self) -> usize {
+        match self {
+            Self::Node4 { nodes, .. } | Self::Node16 { nodes, .. } | Self::Node48 { nodes, .. } => {
+                nodes.len()
+            }
+            Self::Node256 { len, .. } => *len,
+        }
+    }
+
+    fn get(&self, edge: u8) -> Option<&Node> {
+        match self {
+            Self::Node4 { keys, nodes } | Self::Node16 { keys, nodes } => {
+                let i = keys.binary_search(&edge).ok()?;
+                Some(&nodes[i])
+            }
+            Self::Node48 { index, nodes } => match index[edge as usize] {
+                0 => None,
+                i => Some(&nodes[i as usize - 1]),
+            },
+            Self::Node256 { nodes, .. } => nodes[edge as usize].as_ref(),
+        }
+    }
+
+    fn get_mut(&mut self, edge: u8) -> Option<&mut Node> {
+        match self {
+            Self::Node4 { keys, nodes } | Self::Node16 { keys, nodes } => {
+                let i = keys.binary_search(&edge).ok()?;
+                Some(&mut nodes[i])
+            }
+            Self::Node48 { index, nodes } => match index[edge as usize] {
+                0 => None,
+                i => Some(&mut nodes[i as usize - 1]),
+            },
+            Self::Node256 { nodes, .. } => nodes[edge as usize].as_mut(),
+        }
+    }
+
+    /// 插入新的子节点，调用方需保证`edge`不存在
+    fn insert(&mut self, edge: u8, node: Node) {
+        if self.is_full() {
+            self.grow();
+        }
+        match self {
+            Self::Node4 { keys, nodes } | Self::Node16 { keys, nodes } => {
+                let i = keys.binary_search(&edge).unwrap_or_else(|i| i);
+                keys.insert(i, edge);
+                nodes.insert(i, node);
+            }
+            Self::Node48 { index, nodes } => {
+                nodes.push(node);
+                index[edge as usize] = nodes.len() as u8;
+            }
+            Self::Node256 { nodes, len } => {
+                nodes[edge as usize] = Some(node);
+                *len += 1;
+            }
+        }
+    }
+
+    fn remove(&mut self, edge: u8) -> Option<Node> {
+        let node = match self {
+            Self::Node4 { keys, nodes } | Self::Node16 { keys, nodes } => {
+                let i = keys.binary_search(&edge).ok()?;
+                keys.remove(i);
+                nodes.remove(i)
+            }
+            Self::Node48 { index, nodes } => {
+                let i = match index[edge as usize] {
+                    0 => return None,
+                    i => i as usize - 1,
+                };
+                index[edge as usize] = 0;
+                let node = nodes.swap_remove(i);
+                // 修正被移动的子节点的下标
+                if i < nodes.len() {
+                    let moved = index.iter().position(|&x| x as usize == nodes.len() + 1);
+                    if let Some(moved) = moved {
+                        index[moved] = i as u8 + 1;
+                    }
+                }
+                node
+            }
+            Self::Node256 { nodes, len } => {
+                let node = nodes[edge as usize].take()?;
+                *len -= 1;
+                node
+            }
+        };
+        self.shrink();
+        Some(node)
+    }
+
+    /// 移除并返回唯一的子节点
+    fn pop_only(&mut self) -> Option<(u8, Node)> {
+        if self.len() != 1 {
+            return None;
+        }
+        let (edge, _) = self.iter().next()?;
+        self.remove(edge).map(|node| (edge, node))
+    }
+
+    /// 按分支字节的顺序迭代子节点
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = (u8, &Node)> + '_> {
+        match self {
+            Self::Node4 { keys, nodes } | Self::Node16 { keys, nodes } => {
+                Box::new(keys.iter().copied().zip(nodes.iter()))
+            }
+            Self::Node48 { index, nodes } => Box::new(
+                index
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &i)| i > 0)
+                    .map(move |(edge, &i)| (edge as u8, &nodes[i as usize - 1])),
+            ),
+            Self::Node256 { nodes, .. } => Box::new(
+                nodes
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(edge, node)| Some((edge as u8, node.as_ref()?))),
+            ),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        match self {
+            Self::Node4 { nodes, .. } => nodes.len() >= 4,
+            Self::Node16 { nodes, .. } => nodes.len() >= 16,
+            Self::Node48 { nodes, .. } => nodes.len() >= 48,
+            Self::Node256 { .. } => false,
+        }
+    }
+
+    fn grow(&mut self) {
+        let grown = match mem::take(self) {
+            Self::Node4 { keys, nodes } => Self::Node16 { keys, nodes },
+            Self::Node16 { keys, nodes } => {
+                let mut index = Box::new([0; 256]);
+                for (i, edge) in keys.into_iter().enumerate() {
+                    index[edge as usize] = i as u8 + 1;
+                }
+                Self::Node48 { index, nodes }
+            }
+            Self::Node48 { index, mut nodes } => {
+                let mut slots = Box::new([const { None }; 256]);
+                let len = nodes.len();
+                // 逆序取出以保持下标有效
+                let mut edges = index
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &i)| i > 0)
+                    .map(|(edge, &i)| (edge, i as usize - 1))
+                    .collect::<Vec<_>>();
+                edges.sort_unstable_by_key(|&(_, i)| std::cmp::Reverse(i));
+                for (edge, i) in edges {
+                    slots[edge] = Some(nodes.swap_remove(i));
+                }
+                Self::Node256 { nodes: slots, len }
+            }
+            node256 => node256,
+        };
+        *self = grown;
+    }
+
+    fn shrink(&mut self) {
+        let shrunk = match mem::take(self) {
+            Self::Node16 { keys, nodes } if nodes.len() <= 3 => Self::Node4 { keys, nodes },
+            Self::Node48 { index, mut nodes } if nodes.len() <= 12 => {
+                let mut entries = index
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &i)| i > 0)
+                    .map(|(edge, &i)| (edge as u8, i as usize - 1))
+                    .collect::<Vec<_>>();
+                entries.sort_unstable_by_key(|&(_, i)| std::cmp::Reverse(i));
+                let mut children = entries
+                    .into_iter()
+                    .map(|(edge, i)| (edge, nodes.swap_remove(i)))
+                    .collect::<Vec<_>>();
+                children.sort_unstable_by_key(|(edge, _)| *edge);
+                let (keys, nodes) = children.into_iter().unzip();
+                Self::Node16 { keys, nodes }
+            }
+            Self::Node256 { mut nodes, len } if len <= 37 => {
+                let mut index = Box::new([0; 256]);
+                let mut children = Vec::with_capacity(len);
+                for (edge, node) in nodes.iter_mut().enumerate() {
+                    if let Some(node) = node.take() {
+                        children.push(node);
+                        index[edge] = children.len() as u8;
+                    }
+                }
+                Self::Node48 {
+                    index,
+                    nodes: children,
+                }
+            }
+            children => children,
+        };
+        *self = shrunk;
+    }
+}
+
+#[inline]
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+/// 以`prefix`为前缀的 key 是否可能位于`range`内
+fn subtree_may_overlap(prefix: &[u8], (lower, upper): KeyRange) -> bool {
+    // 子树中的 key 均以`prefix`开头，不小于`prefix`，且与不以`prefix`开头的 key 的大小关系同`prefix`
+    let below_lower = match lower {
+        Bound::Included(l) | Bound::Excluded(l) => prefix < l && !l.starts_with(prefix),
+        Bound::Unbounded => false,
+    };
+    let above_upper = match upper {
+        Bound::Included(u) => prefix > u,
+        Bound::Excluded(u) => prefix >= u,
+        Bound::Unbounded => false,
+    };
+    !below_lower && !above_upper
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn pos(n: u64) -> RecordPos {
+        RecordPos {
+            gen: 0,
+            offset: n,
+            size: 1,
+            expire_at: 0,
+        }
+    }
+
+    fn kind(children: &Children) -> &'static str {
+        match children {
+            Children::Node4 { .. } => "node4",
+            Children::Node16 { .. } => "node16",
+            Children::Node48 { .. } => "node48",
+            Children::Node256 { .. } => "node256",
+        }
+    }
+
+    /// 校验子节点集合的容量及有序性，以及路径压缩
+    fn check_node(node: &Node, is_root: bool) {
+        let len = node.children.len();
+        let (min, max) = match node.children {
+            Children::Node4 { .. } => (0, 4),
+            Children::Node16 { .. } => (4, 16),
+            Children::Node48 { .. } => (13, 48),
+            Children::Node256 { .. } => (38, 256),
+        };
+        assert!(
+            (min..=max).contains(&len),
+            "{} with {} children",
+            kind(&node.children),
+            len
+        );
+        match &node.children {
+            Children::Node4 { keys, nodes } | Children::Node16 { keys, nodes } => {
+                assert_eq!(keys.len(), nodes.len());
+                assert!(keys.windows(2).all(|w| w[0] < w[1]));
+            }
+            Children::Node48 { index, nodes } => {
+                let mut slots = index.iter().filter(|&&i| i > 0).collect::<Vec<_>>();
+                slots.sort_unstable();
+                assert_eq!(slots.len(), nodes.len());
+                assert!(slots.iter().enumerate().all(|(i, &&s)| s as usize == i + 1));
+            }
+            Children::Node256 { nodes, len } => {
+                assert_eq!(nodes.iter().flatten().count(), *len);
+            }
+        }
+        if !is_root {
+            // 没有 value 的节点至少有两个子节点，否则应被合并
+            assert!(node.value.is_some() || len >= 2);
+        }
+        for (_, child) in node.children.iter() {
+            check_node(child, false);
+        }
+    }
+
+    /// 通过`RangeSeek`按顺序收集`range`内的全部 key
+    fn collect(tree: &RwLock<Art>, range: KeyRange, reverse: bool) -> Vec<(Vec<u8>, RecordPos)> {
+        let mut items = Vec::new();
+        loop {
+            let last = items
+                .last()
+                .map(|(key, _): &(Vec<u8>, RecordPos)| key.clone());
+            let range = match (&last, reverse) {
+                (None, _) => range,
+                (Some(key), false) => (Bound::Excluded(key.as_slice()), range.1),
+                (Some(key), true) => (range.0, Bound::Excluded(key.as_slice())),
+            };
+            match tree.seek_in(range, reverse, &mut |_| true) {
+                Some(item) => items.push(item),
+                None => return items,
+            }
+        }
+    }
+
+    fn assert_same(tree: &RwLock<Art>, model: &BTreeMap<Vec<u8>, RecordPos>) {
+        let art = tree.read();
+        check_node(&art.root, true);
+        assert_eq!(art.len, model.len());
+        for (key, value) in model {
+            assert_eq!(art.get(key), Some(value));
+        }
+        drop(art);
+
+        let all = (Bound::Unbounded, Bound::Unbounded);
+        let expected = model
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect::<Vec<_>>();
+        assert_eq!(collect(tree, all, false), expected);
+        let mut reversed = expected;
+        reversed.reverse();
+        assert_eq!(collect(tree, all, true), reversed);
+    }
+
+    #[test]
+    fn nodes_grow_and_shrink_through_every_size() {
+        let tree = RwLock::new(Art::default());
+        let mut model = BTreeMap::new();
+        // 根节点的子节点数量依次经过全部节点类型
+        let mut kinds = Vec::new();
+        for edge in 0..=255u8 {
+            let key = vec![edge, b'x'];
+            tree.write().insert(&key, pos(edge as u64));
+            model.insert(key, pos(edge as u64));
+            let kind = kind(&tree.read().root.children);
+            if kinds.last() != Some(&kind) {
+                kinds.push(kind);
+                assert_same(&tree, &model);
+            }
+        }
+        assert_eq!(kinds, ["node4", "node16", "node48", "node256"]);
+        assert_same(&tree, &model);
+
+        let mut kinds = Vec::new();
+        for edge in (0..=255u8).rev() {
+            let key = vec![edge, b'x'];
+            assert_eq!(tree.write().remove(&key), Some(pos(edge as u64)));
+            assert_eq!(tree.write().remove(&key), None);
+            model.remove(&key);
+            let kind = kind(&tree.read().root.children);
+            if kinds.last() != Some(&kind) {
+                kinds.push(kind);
+                assert_same(&tree, &model);
+            }
+        }
+        assert_eq!(kinds, ["node256", "node48", "node16", "node4"]);
+        assert_same(&tree, &model);
+    }
+
+    #[test]
+    fn random_operations_match_btreemap() {
+        // 线性同余生成器，保证测试可重现
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move |n: u64| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 33) % n
+        };
+
+        let tree = RwLock::new(Art::default());
+        let mut model = BTreeMap::new();
+        for round in 0..20_000u64 {
+            // 较小的字母表及长度，产生大量共享前缀及互为前缀的 key
+            let len = next(6) as usize + 1;
+            let alphabet = if round % 2 == 0 { 4 } else { 64 };
+            let key = (0..len)
+                .map(|_| b'a' + next(alphabet) as u8)
+                .collect::<Vec<_>>();
+            if next(3) == 0 {
+                assert_eq!(tree.write().remove(&key), model.remove(&key));
+            } else {
+                assert_eq!(
+                    tree.write().insert(&key, pos(round)),
+                    model.insert(key, pos(round))
+                );
+            }
+            if round % 2_000 == 0 {
+                assert_same(&tree, &model);
+            }
+        }
+        assert_same(&tree, &model);
+
+        // 范围查找
+        for (lower, upper) in [
+            (b"ab".as_slice(), b"b".as_slice()),
+            (b"a", b"aab"),
+            (b"c", b"c"),
+        ] {
+            for (lower, upper) in [
+                (Bound::Included(lower), Bound::Excluded(upper)),
+                (Bound::Excluded(lower), Bound::Included(upper)),
+            ] {
+                let expected = model
+                    .range::<[u8], _>((lower, upper))
+                    .map(|(k, v)| (k.clone(), *v))
+                    .collect::<Vec<_>>();
+                if !crate::index::view::is_empty_range((lower, upper)) {
+                    assert_eq!(collect(&tree, (lower, upper), false), expected);
+                }
+            }
+        }
+
+        // 全部删除后恢复为空树
+        for key in model.keys() {
+            assert!(tree.write().remove(key).is_some());
+        }
+        let art = tree.read();
+        assert_eq!(art.len, 0);
+        assert_eq!(art.root.children.len(), 0);
+        assert!(art.root.value.is_none());
+    }
+
+    #[test]
+    fn iterator_keeps_view_at_creation() {
+        use crate::config::IteratorConfig;
+
+        let index = AdaptiveRadixTree::new();
+        for (i, key) in ["a", "ab", "abc", "b", "ba"].iter().enumerate() {
+            index.put(key.as_bytes().to_vec(), pos(i as u64)).unwrap();
+        }
+        let mut iter = index
+            .iterator(IteratorConfig {
+                prefix: b"a".to_vec(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        index.delete(b"ab").unwrap();
+        index.put(b"abd".to_vec(), pos(10)).unwrap();
+        index.put(b"a".to_vec(), pos(11)).unwrap();
+        index.clear().unwrap();
+
+        let mut keys = Vec::new();
+        while let Some((key, pos)) = iter.next().unwrap() {
+            keys.push((key, pos.offset));
+        }
+        assert_eq!(
+            keys,
+            [
+                (b"a".to_vec(), 0),
+                (b"ab".to_vec(), 1),
+                (b"abc".to_vec(), 2)
+            ]
+        );
+        assert_eq!(index.len().unwrap(), 0);
+    }
+}
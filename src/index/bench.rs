@@ -0,0 +1,39 @@
+use std::path::Path;
+
+use crate::{
+    data::record::{RecordPos, DEFAULT_KEYSPACE, NO_EXPIRE},
+    error::Result,
+};
+
+use super::{new_index, Index, IndexType};
+
+/// 直接读写索引，仅供基准测试使用，需启用`bench` feature
+pub struct BenchIndex {
+    inner: Box<dyn Index>,
+}
+
+impl BenchIndex {
+    /// 创建`index_type`的索引，持久化索引存储于`dir_path`
+    pub fn new(index_type: IndexType, dir_path: &Path) -> Result<Self> {
+        Ok(Self {
+            inner: new_index(index_type, dir_path, DEFAULT_KEYSPACE)?,
+        })
+    }
+
+    /// 写入 key，以`offset`作为其位置
+    pub fn put(&self, key: Vec<u8>, offset: u64) -> Result<()> {
+        let pos = RecordPos {
+            gen: 0,
+            offset,
+            size: 0,
+            expire_at: NO_EXPIRE,
+        };
+        self.inner.put(key, pos)?;
+        Ok(())
+    }
+
+    /// key 是否存在
+    pub fn contains(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.inner.get(key)?.is_some())
+    }
+}
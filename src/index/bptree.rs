@@ -0,0 +1,657 @@
+use std::{
+    cmp::Ordering as CmpOrdering,
+    collections::{BTreeMap, VecDeque},
+    fs,
+    io::ErrorKind,
+    ops::Bound,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::RwLock;
+use redb::{
+    Database, Durability, Range, ReadOnlyTable, ReadTransaction, ReadableTableMetadata,
+    TableDefinition, TableError,
+};
+
+use crate::{
+    config::IteratorConfig,
+    data::record::{RecordPos, DEFAULT_KEYSPACE},
+    error::{KvError, Result},
+    iterator::IndexIterator,
+};
+
+use super::{
+    view::{as_ref, is_empty_range, key_range, max_lower, min_upper, KeyRange},
+    Checkpoint, Index, IndexSnapshot,
+};
+
+const BPTREE_INDEX_FILE_NAME: &str = "bptree.index";
+const BPTREE_INDEX_FILE_PREFIX: &str = "bptree-";
+const BPTREE_INDEX_FILE_SUFFIX: &str = ".index";
+
+/// `RecordPos`在表中的存储形式：(gen, offset, size, expire_at)
+type PosValue = (u32, u64, u32, u64);
+
+const INDEX_TABLE: TableDefinition<&[u8], PosValue> = TableDefinition::new("index");
+const META_TABLE: TableDefinition<&str, u64> = TableDefinition::new("meta");
+
+const META_GEN: &str = "gen";
+const META_OFFSET: &str = "offset";
+const META_SEQ: &str = "seq";
+const META_RECLAIMABLE_SIZE: &str = "reclaimable_size";
+
+/// 页缓存的大小上限
+const CACHE_SIZE: usize = 1024 * 1024 * 64; // 64MB
+
+/// 两次检查点之间允许的最大修改次数，即重启时至多需要重放的`Record`数量
+const CHECKPOINT_INTERVAL: usize = 256;
+
+/// 内存中暂存的修改数量上限，超出时以非持久化事务写入 redb
+///
+/// 构建索引等长时间没有检查点的修改以此限制内存占用
+const FLUSH_INTERVAL: usize = CHECKPOINT_INTERVAL * 4;
+
+/// 尚未写入 redb 的修改，`None`表示 key 已被删除
+type Pending = BTreeMap<Vec<u8>, Option<RecordPos>>;
+
+/// 存储于数据目录中的持久化B+树索引
+///
+/// 修改先暂存于内存，检查点时与检查点一并以一个持久化事务提交，
+/// 暂存的修改过多时以非持久化事务提前写入；只有`save_checkpoint`在数据落盘后持久化提交，
+/// 崩溃后索引回退至最近一次检查点，由 Engine 重放检查点之后的`Record`
+pub(crate) struct BPlusTree {
+    db: Database,
+    /// 快照共享创建时的暂存修改，修改时若被共享则先复制
+    pending: RwLock<Arc<Pending>>,
+    /// 自上次检查点以来的修改次数
+    dirty: AtomicUsize,
+}
+
+impl BPlusTree {
+    pub(crate) fn new(dir_path: &Path, keyspace: u32) -> Result<Self> {
+        let db = Database::builder()
+            .set_cache_size(CACHE_SIZE)
+            .create(dir_path.join(index_file_name(keyspace)))
+            .map_err(index_err)?;
+
+        // 预先创建表，只读事务才能打开
+        let txn = db.begin_write().map_err(index_err)?;
+        // 旧格式的索引连同检查点一并删除，启动时将重新构建
+        if let Err(TableError::TableTypeMismatch { .. }) = txn.open_table(INDEX_TABLE) {
+            txn.delete_table(INDEX_TABLE).map_err(index_err)?;
+            txn.delete_table(META_TABLE).map_err(index_err)?;
+        }
+        txn.open_table(INDEX_TABLE).map_err(index_err)?;
+        txn.open_table(META_TABLE).map_err(index_err)?;
+        txn.commit().map_err(index_err)?;
+
+        Ok(Self {
+            db,
+            pending: RwLock::new(Arc::default()),
+            dirty: AtomicUsize::new(0),
+        })
+    }
+
+    /// key 当前的位置，调用方需持有`pending`的锁
+    fn lookup(&self, pending: &Pending, key: &[u8]) -> Result<Option<RecordPos>> {
+        if let Some(pos) = pending.get(key) {
+            return Ok(*pos);
+        }
+        let txn = self.db.begin_read().map_err(index_err)?;
+        let table = txn.open_table(INDEX_TABLE).map_err(index_err)?;
+        let pos = table.get(key).map_err(index_err)?;
+        Ok(pos.map(|v| from_value(v.value())))
+    }
+
+    /// 暂存 key 的修改，暂存的修改过多时以非持久化事务写入 redb
+    fn stage(
+        &self,
+        pending: &mut Arc<Pending>,
+        key: Vec<u8>,
+        pos: Option<RecordPos>,
+    ) -> Result<()> {
+        Arc::make_mut(pending).insert(key, pos);
+        self.dirty.fetch_add(1, Ordering::SeqCst);
+        if pending.len() >= FLUSH_INTERVAL {
+            self.commit(pending, None)?;
+        }
+        Ok(())
+    }
+
+    /// 将暂存的修改写入 redb，有`checkpoint`时连同检查点持久化提交，否则非持久化提交
+    ///
+    /// 调用方需持有`pending`的写锁
+    fn commit(&self, pending: &mut Arc<Pending>, checkpoint: Option<&Checkpoint>) -> Result<()> {
+        let mut txn = self.db.begin_write().map_err(index_err)?;
+        if checkpoint.is_none() {
+            txn.set_durability(Durability::None);
+        }
+        {
+            let mut table = txn.open_table(INDEX_TABLE).map_err(index_err)?;
+            for (key, pos) in pending.iter() {
+                match pos {
+                    Some(pos) => table.insert(key.as_slice(), to_value(pos)),
+                    None => table.remove(key.as_slice()),
+                }
+                .map_err(index_err)?;
+            }
+        }
+        if let Some(checkpoint) = checkpoint {
+            let mut table = txn.open_table(META_TABLE).map_err(index_err)?;
+            for (name, value) in [
+                (META_GEN, checkpoint.gen as u64),
+                (META_OFFSET, checkpoint.offset),
+                (META_SEQ, checkpoint.seq as u64),
+                (META_RECLAIMABLE_SIZE, checkpoint.reclaimable_size),
+            ] {
+                table.insert(name, value).map_err(index_err)?;
+            }
+        }
+        txn.commit().map_err(index_err)?;
+
+        // 快照可能仍持有原有的暂存修改，不能原地清空
+        *pending = Arc::default();
+        Ok(())
+    }
+}
+
+impl Index for BPlusTree {
+    fn put(&self, key: Vec<u8>, value: RecordPos) -> Result<Option<RecordPos>> {
+        let mut pending = self.pending.write();
+        let old = self.lookup(&pending, &key)?;
+        self.stage(&mut pending, key, Some(value))?;
+        Ok(old)
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<RecordPos>> {
+        self.lookup(&self.pending.read(), key)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<Option<RecordPos>> {
+        let mut pending = self.pending.write();
+        let old = self.lookup(&pending, key)?;
+        if old.is_some() {
+            self.stage(&mut pending, key.to_vec(), None)?;
+        }
+        Ok(old)
+    }
+
+    fn len(&self) -> Result<usize> {
+        let pending = self.pending.read();
+        let txn = self.db.begin_read().map_err(index_err)?;
+        let table = txn.open_table(INDEX_TABLE).map_err(index_err)?;
+        let mut len = table.len().map_err(index_err)? as usize;
+        for (key, pos) in pending.iter() {
+            let stored = table.get(key.as_slice()).map_err(index_err)?.is_some();
+            match (stored, pos.is_some()) {
+                (false, true) => len += 1,
+                (true, false) => len -= 1,
+                _ => {}
+            }
+        }
+        Ok(len)
+    }
+
+    fn compare_and_put(&self, key: &[u8], expected: RecordPos, value: RecordPos) -> Result<bool> {
+        let mut pending = self.pending.write();
+        if self.lookup(&pending, key)? != Some(expected) {
+            return Ok(false);
+        }
+        self.stage(&mut pending, key.to_vec(), Some(value))?;
+        Ok(true)
+    }
+
+    fn clear(&self) -> Result<()> {
+        let mut pending = self.pending.write();
+        let txn = self.db.begin_write().map_err(index_err)?;
+        txn.delete_table(INDEX_TABLE).map_err(index_err)?;
+        txn.delete_table(META_TABLE).map_err(index_err)?;
+        txn.open_table(INDEX_TABLE).map_err(index_err)?;
+        txn.open_table(META_TABLE).map_err(index_err)?;
+        txn.commit().map_err(index_err)?;
+
+        *pending = Arc::default();
+        self.dirty.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn IndexSnapshot>> {
+        // 只读事务及暂存的修改共同构成创建时的一致快照
+        let pending = self.pending.read();
+        let txn = self.db.begin_read().map_err(index_err)?;
+        Ok(Box::new(BPlusTreeSnapshot {
+            txn,
+            pending: pending.clone(),
+        }))
+    }
+
+    fn checkpoint(&self) -> Result<Option<Checkpoint>> {
+        let txn = self.db.begin_read().map_err(index_err)?;
+        let table = txn.open_table(META_TABLE).map_err(index_err)?;
+        let get = |name| -> Result<Option<u64>> {
+            Ok(table.get(name).map_err(index_err)?.map(|v| v.value()))
+        };
+
+        let (Some(gen), Some(offset), Some(seq), Some(reclaimable_size)) = (
+            get(META_GEN)?,
+            get(META_OFFSET)?,
+            get(META_SEQ)?,
+            get(META_RECLAIMABLE_SIZE)?,
+        ) else {
+            return Ok(None);
+        };
+        Ok(Some(Checkpoint {
+            gen: gen as u32,
+            offset,
+            seq: seq as usize,
+            reclaimable_size,
+        }))
+    }
+
+    fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<()> {
+        // 暂存的修改与检查点在同一持久化事务中提交，此前的非持久化提交一并落盘
+        let mut pending = self.pending.write();
+        self.commit(&mut pending, Some(checkpoint))?;
+        self.dirty.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn checkpoint_due(&self) -> bool {
+        self.dirty.load(Ordering::SeqCst) >= CHECKPOINT_INTERVAL
+    }
+}
+
+struct BPlusTreeSnapshot {
+    txn: ReadTransaction,
+    pending: Arc<Pending>,
+}
+
+impl IndexSnapshot for BPlusTreeSnapshot {
+    fn get(&self, key: &[u8]) -> Result<Option<RecordPos>> {
+        if let Some(pos) = self.pending.get(key) {
+            return Ok(*pos);
+        }
+        let table = self.txn.open_table(INDEX_TABLE).map_err(index_err)?;
+        let pos = table.get(key).map_err(index_err)?;
+        Ok(pos.map(|v| from_value(v.value())))
+    }
+
+    fn iterator(&self, config: IteratorConfig) -> Result<Box<dyn IndexIterator>> {
+        let table = self.txn.open_table(INDEX_TABLE).map_err(index_err)?;
+        let (lower, upper) = key_range(&config);
+        let mut iter = BPlusTreeIterator {
+            table,
+            range: None,
+            stored: None,
+            pending: self.pending.clone(),
+            staged: VecDeque::new(),
+            lower,
+            upper,
+            reverse: config.reverse,
+            error: None,
+        };
+        iter.rewind();
+        Ok(Box::new(iter))
+    }
+}
+
+/// 合并 redb 中的内容与暂存的修改进行迭代，相同的 key 以暂存的修改为准
+pub(crate) struct BPlusTreeIterator {
+    table: ReadOnlyTable<&'static [u8], PosValue>,
+    range: Option<Range<'static, &'static [u8], PosValue>>,
+    /// redb 中下一个待比较的 key
+    stored: Option<(Vec<u8>, RecordPos)>,
+    pending: Arc<Pending>,
+    /// 迭代范围内按迭代顺序排列的暂存修改
+    staged: VecDeque<(Vec<u8>, Option<RecordPos>)>,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    reverse: bool,
+    /// 定位失败时的错误，由下一次`next`返回
+    error: Option<KvError>,
+}
+
+impl BPlusTreeIterator {
+    /// 在`range`内重新开始迭代
+    fn reset(&mut self, range: KeyRange) {
+        self.range = None;
+        self.stored = None;
+        self.staged.clear();
+        self.error = None;
+        if is_empty_range(range) {
+            return;
+        }
+
+        let staged = self
+            .pending
+            .range::<[u8], _>(range)
+            .map(|(key, pos)| (key.clone(), *pos));
+        self.staged = if self.reverse {
+            staged.rev().collect()
+        } else {
+            staged.collect()
+        };
+        match self.table.range::<&[u8]>(range) {
+            Ok(range) => self.range = Some(range),
+            Err(e) => self.error = Some(index_err(e)),
+        }
+    }
+
+    /// redb 中的下一个 key
+    fn next_stored(&mut self) -> Result<Option<(Vec<u8>, RecordPos)>> {
+        let Some(range) = self.range.as_mut() else {
+            return Ok(None);
+        };
+        let item = if self.reverse {
+            range.next_back()
+        } else {
+            range.next()
+        };
+        let (key, value) = match item {
+            Some(Ok(item)) => item,
+            Some(Err(e)) => {
+                self.range = None;
+                return Err(index_err(e));
+            }
+            None => return Ok(None),
+        };
+
+        Ok(Some((key.value().to_vec(), from_value(value.value()))))
+    }
+}
+
+impl IndexIterator for BPlusTreeIterator {
+    fn rewind(&mut self) {
+        let lower = self.lower.clone();
+        let upper = self.upper.clone();
+        self.reset((as_ref(&lower), as_ref(&upper)));
+    }
+
+    fn seek(&mut self, key: Vec<u8>) {
+        let key = Bound::Included(key);
+        let lower = self.lower.clone();
+        let upper = self.upper.clone();
+        let range = if self.reverse {
+            (as_ref(&lower), min_upper(&upper, &key))
+        } else {
+            (max_lower(&lower, &key), as_ref(&upper))
+        };
+        self.reset(range);
+    }
+
+    fn next(&mut self) -> Result<Option<(Vec<u8>, RecordPos)>> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        loop {
+            if self.stored.is_none() {
+                self.stored = self.next_stored()?;
+            }
+            let take_staged = match (self.staged.front(), &self.stored) {
+                (None, None) => return Ok(None),
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some((key, _)), Some((stored_key, _))) => match key.cmp(stored_key) {
+                    // 暂存的修改覆盖 redb 中的内容
+                    CmpOrdering::Equal => {
+                        self.stored = None;
+                        true
+                    }
+                    ord => (ord == CmpOrdering::Less) != self.reverse,
+                },
+            };
+            if !take_staged {
+                return Ok(self.stored.take());
+            }
+            // 跳过已被删除的 key
+            if let Some((key, Some(pos))) = self.staged.pop_front() {
+                return Ok(Some((key, pos)));
+            }
+        }
+    }
+}
+
+/// `keyspace`的持久化索引文件名，默认 keyspace 沿用原有的文件名
+fn index_file_name(keyspace: u32) -> String {
+    match keyspace {
+        DEFAULT_KEYSPACE => BPTREE_INDEX_FILE_NAME.to_string(),
+        keyspace => format!(
+            "{}{}{}",
+            BPTREE_INDEX_FILE_PREFIX, keyspace, BPTREE_INDEX_FILE_SUFFIX
+        ),
+    }
+}
+
+/// 数据目录中存在持久化索引文件的全部 keyspace
+pub(crate) fn index_file_keyspaces(dir_path: &Path) -> Result<Vec<u32>> {
+    let mut keyspaces = Vec::new();
+    for entry in fs::read_dir(dir_path)? {
+        let name = entry?.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name == BPTREE_INDEX_FILE_NAME {
+            keyspaces.push(DEFAULT_KEYSPACE);
+        } else if let Some(Ok(keyspace)) = name
+            .strip_prefix(BPTREE_INDEX_FILE_PREFIX)
+            .and_then(|s| s.strip_suffix(BPTREE_INDEX_FILE_SUFFIX))
+            .map(str::parse::<u32>)
+        {
+            keyspaces.push(keyspace);
+        }
+    }
+    Ok(keyspaces)
+}
+
+/// 删除`keyspace`的持久化索引文件，文件不存在时忽略
+pub(crate) fn remove_index_file(dir_path: &Path, keyspace: u32) -> Result<()> {
+    match fs::remove_file(dir_path.join(index_file_name(keyspace))) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[inline]
+fn to_value(pos: &RecordPos) -> PosValue {
+    (pos.gen, pos.offset, pos.size, pos.expire_at)
+}
+
+#[inline]
+fn from_value((gen, offset, size, expire_at): PosValue) -> RecordPos {
+    RecordPos {
+        gen,
+        offset,
+        size,
+        expire_at,
+    }
+}
+
+#[inline]
+fn index_err<E: Into<redb::Error>>(e: E) -> KvError {
+    KvError::IndexError(Box::new(e.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::data::record::NO_EXPIRE;
+
+    fn pos(n: u64) -> RecordPos {
+        RecordPos {
+            gen: 0,
+            offset: n,
+            size: 1,
+            expire_at: NO_EXPIRE,
+        }
+    }
+
+    fn checkpoint(offset: u64) -> Checkpoint {
+        Checkpoint {
+            gen: 0,
+            offset,
+            seq: 0,
+            reclaimable_size: 0,
+        }
+    }
+
+    fn collect(iter: &mut dyn IndexIterator) -> Vec<(Vec<u8>, RecordPos)> {
+        let mut items = Vec::new();
+        while let Some(item) = iter.next().unwrap() {
+            items.push(item);
+        }
+        items
+    }
+
+    fn reverse() -> IteratorConfig {
+        IteratorConfig {
+            reverse: true,
+            ..Default::default()
+        }
+    }
+
+    /// 比较索引及其迭代器与`BTreeMap`的内容
+    fn assert_same(index: &BPlusTree, expected: &BTreeMap<Vec<u8>, RecordPos>) {
+        let items = expected
+            .iter()
+            .map(|(key, pos)| (key.clone(), *pos))
+            .collect::<Vec<_>>();
+        assert_eq!(index.len().unwrap(), items.len());
+
+        let forward = collect(index.iterator(IteratorConfig::default()).unwrap().as_mut());
+        assert_eq!(forward, items);
+        let backward = collect(index.iterator(reverse()).unwrap().as_mut());
+        assert_eq!(backward, items.into_iter().rev().collect::<Vec<_>>());
+
+        let mut iter = index.iterator(IteratorConfig::default()).unwrap();
+        iter.seek(b"k05".to_vec());
+        let seeked = collect(iter.as_mut());
+        assert!(seeked
+            .iter()
+            .all(|(key, _)| key.as_slice() >= b"k05".as_slice()));
+        assert_eq!(seeked.len(), expected.range(b"k05".to_vec()..).count());
+    }
+
+    #[test]
+    fn random_operations_match_btreemap() {
+        let dir = TempDir::new().unwrap();
+        let index = BPlusTree::new(dir.path(), DEFAULT_KEYSPACE).unwrap();
+        let mut expected = BTreeMap::new();
+
+        // 线性同余生成器，保证结果可复现
+        let mut state = 7u64;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            state >> 33
+        };
+        // 操作数超过`FLUSH_INTERVAL`，覆盖暂存、非持久化提交及检查点
+        for i in 0..FLUSH_INTERVAL as u64 * 3 {
+            let key = format!("k{:03}", next() % 300).into_bytes();
+            match next() % 10 {
+                0..=5 => assert_eq!(
+                    index.put(key.clone(), pos(i)).unwrap(),
+                    expected.insert(key, pos(i))
+                ),
+                6..=8 => assert_eq!(index.delete(&key).unwrap(), expected.remove(&key)),
+                _ => {
+                    // 一半的情况下以当前位置比较，其余情况下比较必然失败
+                    let current = expected.get(&key).copied();
+                    let compared = match next() % 2 {
+                        0 => current.unwrap_or(pos(u64::MAX)),
+                        _ => pos(u64::MAX),
+                    };
+                    let updated = current == Some(compared);
+                    assert_eq!(
+                        index.compare_and_put(&key, compared, pos(i)).unwrap(),
+                        updated
+                    );
+                    if updated {
+                        expected.insert(key, pos(i));
+                    }
+                }
+            }
+            if i % 300 == 0 {
+                index.save_checkpoint(&checkpoint(i)).unwrap();
+            }
+            if i % 100 == 0 {
+                assert_same(&index, &expected);
+            }
+        }
+        assert_same(&index, &expected);
+    }
+
+    #[test]
+    fn snapshot_keeps_view_at_creation() {
+        let dir = TempDir::new().unwrap();
+        let index = BPlusTree::new(dir.path(), DEFAULT_KEYSPACE).unwrap();
+        for i in 0..10 {
+            index
+                .put(format!("k{:02}", i).into_bytes(), pos(i))
+                .unwrap();
+        }
+        index.save_checkpoint(&checkpoint(1)).unwrap();
+        // 一部分修改仍暂存于内存中
+        index.delete(b"k03").unwrap();
+        index.put(b"k10".to_vec(), pos(10)).unwrap();
+
+        let snapshot = index.snapshot().unwrap();
+        let before = collect(
+            snapshot
+                .iterator(IteratorConfig::default())
+                .unwrap()
+                .as_mut(),
+        );
+
+        index.put(b"k03".to_vec(), pos(30)).unwrap();
+        index.delete(b"k05").unwrap();
+        index.put(b"k11".to_vec(), pos(11)).unwrap();
+        index.save_checkpoint(&checkpoint(2)).unwrap();
+        index.delete(b"k06").unwrap();
+
+        assert_eq!(snapshot.get(b"k03").unwrap(), None);
+        assert_eq!(snapshot.get(b"k05").unwrap(), Some(pos(5)));
+        assert_eq!(snapshot.get(b"k06").unwrap(), Some(pos(6)));
+        assert_eq!(
+            collect(
+                snapshot
+                    .iterator(IteratorConfig::default())
+                    .unwrap()
+                    .as_mut()
+            ),
+            before
+        );
+        assert_eq!(before.len(), 10);
+        assert_eq!(index.len().unwrap(), 10);
+    }
+
+    #[test]
+    fn only_checkpointed_changes_survive_reopen() {
+        let dir = TempDir::new().unwrap();
+        let index = BPlusTree::new(dir.path(), DEFAULT_KEYSPACE).unwrap();
+        index.put(b"a".to_vec(), pos(1)).unwrap();
+        index.put(b"b".to_vec(), pos(2)).unwrap();
+        index.save_checkpoint(&checkpoint(10)).unwrap();
+        // 检查点之后的修改尚未持久化
+        index.put(b"c".to_vec(), pos(3)).unwrap();
+        index.delete(b"a").unwrap();
+        assert!(!index.checkpoint_due());
+        drop(index);
+
+        let index = BPlusTree::new(dir.path(), DEFAULT_KEYSPACE).unwrap();
+        assert!(index.checkpoint().unwrap() == Some(checkpoint(10)));
+        assert_eq!(index.get(b"a").unwrap(), Some(pos(1)));
+        assert_eq!(index.get(b"b").unwrap(), Some(pos(2)));
+        assert_eq!(index.get(b"c").unwrap(), None);
+
+        index.clear().unwrap();
+        assert!(index.checkpoint().unwrap().is_none());
+        assert_eq!(index.len().unwrap(), 0);
+    }
+}
@@ -2,92 +2,95 @@ use std::{collections::BTreeMap, sync::Arc};
 
 use parking_lot::RwLock;
 
-use crate::{config::IteratorConfig, data::record::RecordPos, iterator::IndexIterator};
+use crate::{data::record::RecordPos, error::Result};
 
-use super::Index;
+use super::{
+    view::{CursorSnapshot, KeyRange, RangeSeek, Views},
+    Index, IndexSnapshot,
+};
 
 pub(crate) struct BTree {
     map: Arc<RwLock<BTreeMap<Vec<u8>, RecordPos>>>,
+    views: Views,
 }
 
 impl BTree {
     pub(crate) fn new() -> Self {
         Self {
             map: Arc::new(RwLock::new(BTreeMap::new())),
+            views: Views::default(),
         }
     }
 }
 
 impl Index for BTree {
-    fn put(&self, key: Vec<u8>, value: RecordPos) {
+    fn put(&self, key: Vec<u8>, value: RecordPos) -> Result<Option<RecordPos>> {
         let mut guard = self.map.write();
-        guard.insert(key, value);
+        self.views.record(&key, || guard.get(&key).copied());
+        Ok(guard.insert(key, value))
     }
 
-    fn get(&self, key: &[u8]) -> Option<RecordPos> {
+    fn get(&self, key: &[u8]) -> Result<Option<RecordPos>> {
         let guard = self.map.read();
-        guard.get(key).copied()
+        Ok(guard.get(key).copied())
     }
 
-    fn delete(&self, key: &[u8]) {
+    fn delete(&self, key: &[u8]) -> Result<Option<RecordPos>> {
         let mut guard = self.map.write();
-        guard.remove(key);
+        self.views.record(key, || guard.get(key).copied());
+        Ok(guard.remove(key))
     }
 
-    fn iterator(&self, config: IteratorConfig) -> Box<dyn IndexIterator> {
-        let read_guard = self.map.read();
-        let mut items = read_guard
-            .iter()
-            .map(|map| (map.0.clone(), *map.1))
-            .collect::<Vec<_>>();
+    fn len(&self) -> Result<usize> {
+        Ok(self.map.read().len())
+    }
 
-        if config.reverse {
-            items.reverse()
+    fn compare_and_put(&self, key: &[u8], expected: RecordPos, value: RecordPos) -> Result<bool> {
+        let mut guard = self.map.write();
+        match guard.get_mut(key) {
+            Some(pos) if *pos == expected => {
+                self.views.record(key, || Some(expected));
+                *pos = value;
+                Ok(true)
+            }
+            _ => Ok(false),
         }
-        Box::new(BTreeIterator {
-            items,
-            current_index: 0,
-            config,
-        })
     }
-}
 
-pub struct BTreeIterator {
-    items: Vec<(Vec<u8>, RecordPos)>,
-    current_index: usize,
-    config: IteratorConfig,
-}
-
-impl IndexIterator for BTreeIterator {
-    fn rewind(&mut self) {
-        self.current_index = 0;
+    fn clear(&self) -> Result<()> {
+        let mut guard = self.map.write();
+        if !self.views.is_empty() {
+            for (key, pos) in guard.iter() {
+                self.views.record(key, || Some(*pos));
+            }
+        }
+        guard.clear();
+        Ok(())
     }
 
-    fn seek(&mut self, key: Vec<u8>) {
-        self.current_index = match self.items.binary_search_by(|(res, _)| {
-            if self.config.reverse {
-                res.cmp(&key).reverse()
-            } else {
-                res.cmp(&key)
-            }
-        }) {
-            Ok(value) => value,
-            Err(index) => index,
+    fn snapshot(&self) -> Result<Box<dyn IndexSnapshot>> {
+        let undo = {
+            let _guard = self.map.read();
+            self.views.register()
         };
+        Ok(Box::new(CursorSnapshot::new(self.map.clone(), undo)))
     }
+}
 
-    fn next(&mut self) -> Option<(&Vec<u8>, &RecordPos)> {
-        if self.current_index >= self.items.len() {
-            return None;
-        }
-
-        while let Some(item) = self.items.get(self.current_index) {
-            self.current_index += 1;
-            let prefix = &self.config.prefix;
-            if prefix.is_empty() || item.0.starts_with(prefix) {
-                return Some((&item.0, &item.1));
-            }
-        }
-        None
+impl RangeSeek for RwLock<BTreeMap<Vec<u8>, RecordPos>> {
+    fn seek_in(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        accept: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Option<(Vec<u8>, RecordPos)> {
+        let guard = self.read();
+        let mut iter = guard.range::<[u8], _>(range);
+        let found = if reverse {
+            iter.rfind(|(key, _)| accept(key))
+        } else {
+            iter.find(|(key, _)| accept(key))
+        };
+        found.map(|(key, pos)| (key.clone(), *pos))
     }
 }
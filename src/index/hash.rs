@@ -0,0 +1,328 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    sync::Arc,
+};
+
+use parking_lot::RwLock;
+
+use crate::{
+    config::IteratorConfig, data::record::RecordPos, error::Result, iterator::IndexIterator,
+};
+
+use super::{
+    view::{as_ref, key_range, range_contains, UndoLog, Views},
+    Index, IndexSnapshot,
+};
+
+/// 分片数量，为2的幂以便取模
+const SHARD_NUM: usize = 32;
+
+type Shard = RwLock<HashMap<Vec<u8>, RecordPos>>;
+
+/// 按 key 的哈希值分片的索引，各分片独立加锁，适用于只有点查询的场景
+pub(crate) struct HashSharded {
+    shards: Arc<Shards>,
+    views: Views,
+}
+
+struct Shards {
+    shards: Box<[Shard]>,
+    hasher: RandomState,
+}
+
+impl Shards {
+    #[inline]
+    fn shard(&self, key: &[u8]) -> &Shard {
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.shards[hash & (SHARD_NUM - 1)]
+    }
+}
+
+impl HashSharded {
+    pub(crate) fn new() -> Self {
+        Self {
+            shards: Arc::new(Shards {
+                shards: (0..SHARD_NUM)
+                    .map(|_| RwLock::new(HashMap::new()))
+                    .collect(),
+                hasher: RandomState::new(),
+            }),
+            views: Views::default(),
+        }
+    }
+}
+
+impl Index for HashSharded {
+    fn put(&self, key: Vec<u8>, value: RecordPos) -> Result<Option<RecordPos>> {
+        let mut guard = self.shards.shard(&key).write();
+        self.views.record(&key, || guard.get(&key).copied());
+        Ok(guard.insert(key, value))
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<RecordPos>> {
+        Ok(self.shards.shard(key).read().get(key).copied())
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<Option<RecordPos>> {
+        let mut guard = self.shards.shard(key).write();
+        self.views.record(key, || guard.get(key).copied());
+        Ok(guard.remove(key))
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self
+            .shards
+            .shards
+            .iter()
+            .map(|shard| shard.read().len())
+            .sum())
+    }
+
+    fn compare_and_put(&self, key: &[u8], expected: RecordPos, value: RecordPos) -> Result<bool> {
+        let mut guard = self.shards.shard(key).write();
+        match guard.get_mut(key) {
+            Some(pos) if *pos == expected => {
+                self.views.record(key, || Some(expected));
+                *pos = value;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn clear(&self) -> Result<()> {
+        for shard in self.shards.shards.iter() {
+            let mut guard = shard.write();
+            if !self.views.is_empty() {
+                for (key, pos) in guard.iter() {
+                    self.views.record(key, || Some(*pos));
+                }
+            }
+            guard.clear();
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn IndexSnapshot>> {
+        // 同时持有全部分片的读锁，保证注册时没有进行中的修改
+        let undo = {
+            let _guards = self
+                .shards
+                .shards
+                .iter()
+                .map(|shard| shard.read())
+                .collect::<Vec<_>>();
+            self.views.register()
+        };
+        Ok(Box::new(HashSnapshot {
+            shards: self.shards.clone(),
+            undo,
+        }))
+    }
+}
+
+struct HashSnapshot {
+    shards: Arc<Shards>,
+    undo: Arc<UndoLog>,
+}
+
+impl IndexSnapshot for HashSnapshot {
+    fn get(&self, key: &[u8]) -> Result<Option<RecordPos>> {
+        // 先读取索引再检查`UndoLog`，期间被修改的 key 必然已被记录
+        let live = self.shards.shard(key).read().get(key).copied();
+        Ok(match self.undo.get(key) {
+            Some(old) => old,
+            None => live,
+        })
+    }
+
+    /// 哈希索引无序，需要收集全部分片中的 key 并排序
+    fn iterator(&self, config: IteratorConfig) -> Result<Box<dyn IndexIterator>> {
+        let (lower, upper) = key_range(&config);
+        let range = (as_ref(&lower), as_ref(&upper));
+
+        // 同时持有全部分片的读锁，期间`UndoLog`不会改变
+        let guards = self
+            .shards
+            .shards
+            .iter()
+            .map(|shard| shard.read())
+            .collect::<Vec<_>>();
+        let mut items = guards
+            .iter()
+            .flat_map(|guard| guard.iter())
+            .filter(|(key, _)| range_contains(range, key) && !self.undo.contains(key))
+            .map(|(key, pos)| (key.clone(), *pos))
+            .collect::<Vec<_>>();
+        items.extend(self.undo.collect_in(range));
+        drop(guards);
+
+        items.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        if config.reverse {
+            items.reverse()
+        }
+        Ok(Box::new(HashShardedIterator {
+            items,
+            current_index: 0,
+            reverse: config.reverse,
+        }))
+    }
+}
+
+pub(crate) struct HashShardedIterator {
+    items: Vec<(Vec<u8>, RecordPos)>,
+    current_index: usize,
+    reverse: bool,
+}
+
+impl IndexIterator for HashShardedIterator {
+    fn rewind(&mut self) {
+        self.current_index = 0;
+    }
+
+    fn seek(&mut self, key: Vec<u8>) {
+        self.current_index = match self.items.binary_search_by(|(res, _)| {
+            if self.reverse {
+                res.cmp(&key).reverse()
+            } else {
+                res.cmp(&key)
+            }
+        }) {
+            Ok(value) => value,
+            Err(index) => index,
+        };
+    }
+
+    fn next(&mut self) -> Result<Option<(Vec<u8>, RecordPos)>> {
+        let Some(item) = self.items.get(self.current_index) else {
+            return Ok(None);
+        };
+        self.current_index += 1;
+        Ok(Some(item.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(n: u64) -> RecordPos {
+        RecordPos {
+            gen: 0,
+            offset: n,
+            size: 1,
+            expire_at: 0,
+        }
+    }
+
+    fn key(i: u64) -> Vec<u8> {
+        format!("key-{:04}", i).into_bytes()
+    }
+
+    fn collect(iter: &mut dyn IndexIterator) -> Vec<(Vec<u8>, RecordPos)> {
+        let mut items = Vec::new();
+        while let Some(item) = iter.next().unwrap() {
+            items.push(item);
+        }
+        items
+    }
+
+    #[test]
+    fn put_get_delete_across_shards() {
+        let index = HashSharded::new();
+        for i in 0..1000 {
+            assert_eq!(index.put(key(i), pos(i)).unwrap(), None);
+        }
+        // key 分布于多个分片
+        assert!(
+            index
+                .shards
+                .shards
+                .iter()
+                .filter(|shard| !shard.read().is_empty())
+                .count()
+                > 1
+        );
+        assert_eq!(index.len().unwrap(), 1000);
+
+        assert_eq!(index.put(key(1), pos(2000)).unwrap(), Some(pos(1)));
+        assert_eq!(index.get(&key(1)).unwrap(), Some(pos(2000)));
+        assert!(!index.compare_and_put(&key(1), pos(1), pos(3000)).unwrap());
+        assert!(index
+            .compare_and_put(&key(1), pos(2000), pos(3000))
+            .unwrap());
+        assert_eq!(index.get(&key(1)).unwrap(), Some(pos(3000)));
+
+        for i in (0..1000).step_by(2) {
+            assert_eq!(index.delete(&key(i)).unwrap(), Some(pos(i)));
+        }
+        assert_eq!(index.delete(&key(0)).unwrap(), None);
+        assert_eq!(index.get(&key(0)).unwrap(), None);
+        assert_eq!(index.get(&key(3)).unwrap(), Some(pos(3)));
+        assert_eq!(index.len().unwrap(), 500);
+
+        index.clear().unwrap();
+        assert_eq!(index.len().unwrap(), 0);
+        assert_eq!(index.get(&key(3)).unwrap(), None);
+    }
+
+    #[test]
+    fn iteration_is_sorted() {
+        let index = HashSharded::new();
+        for i in (0..100).rev() {
+            index.put(key(i), pos(i)).unwrap();
+        }
+        let expected = (0..100).map(|i| (key(i), pos(i))).collect::<Vec<_>>();
+
+        let mut iter = index.iterator(IteratorConfig::default()).unwrap();
+        assert_eq!(collect(iter.as_mut()), expected);
+
+        let mut iter = index
+            .iterator(IteratorConfig {
+                reverse: true,
+                ..Default::default()
+            })
+            .unwrap();
+        let mut reversed = expected.clone();
+        reversed.reverse();
+        assert_eq!(collect(iter.as_mut()), reversed);
+        iter.seek(key(10));
+        assert_eq!(collect(iter.as_mut()), reversed[89..]);
+
+        let mut iter = index
+            .iterator(IteratorConfig {
+                prefix: b"key-005".to_vec(),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(collect(iter.as_mut()), expected[50..60]);
+        iter.seek(key(55));
+        assert_eq!(collect(iter.as_mut()), expected[55..60]);
+        iter.rewind();
+        assert_eq!(collect(iter.as_mut()), expected[50..60]);
+    }
+
+    #[test]
+    fn snapshot_keeps_view_at_creation() {
+        let index = HashSharded::new();
+        for i in 0..10 {
+            index.put(key(i), pos(i)).unwrap();
+        }
+        let snapshot = index.snapshot().unwrap();
+
+        index.put(key(0), pos(100)).unwrap();
+        index.delete(&key(1)).unwrap();
+        index.put(key(10), pos(10)).unwrap();
+
+        assert_eq!(snapshot.get(&key(0)).unwrap(), Some(pos(0)));
+        assert_eq!(snapshot.get(&key(1)).unwrap(), Some(pos(1)));
+        assert_eq!(snapshot.get(&key(10)).unwrap(), None);
+        let mut iter = snapshot.iterator(IteratorConfig::default()).unwrap();
+        assert_eq!(
+            collect(iter.as_mut()),
+            (0..10).map(|i| (key(i), pos(i))).collect::<Vec<_>>()
+        );
+        assert_eq!(index.get(&key(0)).unwrap(), Some(pos(100)));
+    }
+}
@@ -1,24 +1,138 @@
+mod art;
+#[cfg(feature = "bench")]
+mod bench;
+mod bptree;
 mod btree;
+mod hash;
+mod skiplist;
+mod view;
 
-use crate::{config::IteratorConfig, data::record::RecordPos, iterator::IndexIterator};
+use std::path::Path;
+
+#[cfg(feature = "bench")]
+pub use bench::BenchIndex;
+
+use crate::{
+    config::IteratorConfig, data::record::RecordPos, error::Result, iterator::IndexIterator,
+};
 
 pub(crate) trait Index: Sync + Send {
-    fn put(&self, key: Vec<u8>, value: RecordPos);
+    /// 存储 key 的位置，返回被覆盖的旧位置
+    fn put(&self, key: Vec<u8>, value: RecordPos) -> Result<Option<RecordPos>>;
+
+    fn get(&self, key: &[u8]) -> Result<Option<RecordPos>>;
+
+    /// 删除 key，返回被删除的位置
+    fn delete(&self, key: &[u8]) -> Result<Option<RecordPos>>;
+
+    /// 索引中 key 的数量
+    fn len(&self) -> Result<usize>;
+
+    /// 仅当 key 当前的位置为 expected 时更新为 value，返回是否更新成功
+    fn compare_and_put(&self, key: &[u8], expected: RecordPos, value: RecordPos) -> Result<bool>;
+
+    /// 清空索引，持久化索引同时清除其检查点
+    fn clear(&self) -> Result<()>;
+
+    /// 创建索引当前内容的一致快照，此后的修改对快照不可见
+    fn snapshot(&self) -> Result<Box<dyn IndexSnapshot>>;
+
+    /// 创建迭代器，迭代的内容为创建时的一致视图
+    fn iterator(&self, config: IteratorConfig) -> Result<Box<dyn IndexIterator>> {
+        self.snapshot()?.iterator(config)
+    }
+
+    /// 持久化索引最近一次保存的检查点，内存索引总是返回`None`
+    fn checkpoint(&self) -> Result<Option<Checkpoint>> {
+        Ok(None)
+    }
+
+    /// 持久化索引当前的内容及检查点，调用方需保证检查点之前的`Record`均已应用至索引
+    fn save_checkpoint(&self, _checkpoint: &Checkpoint) -> Result<()> {
+        Ok(())
+    }
 
-    fn get(&self, key: &[u8]) -> Option<RecordPos>;
+    /// 自上次检查点以来未持久化的修改是否过多，需要保存新的检查点
+    fn checkpoint_due(&self) -> bool {
+        false
+    }
+}
 
-    fn delete(&self, key: &[u8]);
+/// 索引在某一时刻的只读视图
+pub(crate) trait IndexSnapshot: Sync + Send {
+    fn get(&self, key: &[u8]) -> Result<Option<RecordPos>>;
 
-    fn iterator(&self, config: IteratorConfig) -> Box<dyn IndexIterator>;
+    /// 在快照上创建迭代器，同一快照上的迭代器看到相同的内容
+    fn iterator(&self, config: IteratorConfig) -> Result<Box<dyn IndexIterator>>;
+}
+
+/// 持久化索引的检查点，启动时只需重放位于检查点之后的`Record`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Checkpoint {
+    /// 检查点所在的`Storage`
+    pub(crate) gen: u32,
+    /// 检查点在`Storage`中的偏移
+    pub(crate) offset: u64,
+    /// 检查点时已使用的最大批量写入序列号
+    pub(crate) seq: usize,
+    /// 检查点时可被合并回收的字节数
+    pub(crate) reclaimable_size: u64,
+}
+
+impl Checkpoint {
+    /// `pos`处的`Record`是否位于检查点之后
+    #[inline]
+    pub(crate) fn needs_replay(&self, pos: &RecordPos) -> bool {
+        pos.gen > self.gen || (pos.gen == self.gen && pos.offset >= self.offset)
+    }
 }
 
 #[derive(Clone, Copy)]
 pub enum IndexType {
     BTree,
+    /// 自适应基数树，适用于大量共享前缀的 key
+    ART,
+    /// 并发跳表，读操作无需加锁
+    SkipList,
+    /// 存储于数据目录中的持久化B+树，启动时无需扫描全部`Storage`，内存占用有界
+    BPlusTree,
+    /// 分片哈希表，适用于只有点查询的场景，迭代时需要排序全部 key
+    HashSharded,
+}
+
+/// 创建`keyspace`的索引
+pub(crate) fn new_index(
+    index_type: IndexType,
+    dir_path: &Path,
+    keyspace: u32,
+) -> Result<Box<dyn Index>> {
+    Ok(match index_type {
+        IndexType::BTree => Box::new(btree::BTree::new()),
+        IndexType::ART => Box::new(art::AdaptiveRadixTree::new()),
+        IndexType::SkipList => Box::new(skiplist::SkipList::new()),
+        IndexType::BPlusTree => Box::new(bptree::BPlusTree::new(dir_path, keyspace)?),
+        IndexType::HashSharded => Box::new(hash::HashSharded::new()),
+    })
 }
 
-pub(crate) fn new_index(index_type: IndexType) -> impl Index {
+/// 删除`keyspace`的持久化索引文件，用于删除或清空 keyspace
+pub(crate) fn remove_index(index_type: IndexType, dir_path: &Path, keyspace: u32) -> Result<()> {
     match index_type {
-        IndexType::BTree => btree::BTree::new(),
+        IndexType::BPlusTree => bptree::remove_index_file(dir_path, keyspace),
+        _ => Ok(()),
+    }
+}
+
+/// 删除数据目录中不属于`keyspaces`的持久化索引文件，使用内存索引时全部删除
+pub(crate) fn remove_unused_indexes(
+    index_type: IndexType,
+    dir_path: &Path,
+    keyspaces: &[u32],
+) -> Result<()> {
+    for keyspace in bptree::index_file_keyspaces(dir_path)? {
+        if !matches!(index_type, IndexType::BPlusTree) || !keyspaces.contains(&keyspace) {
+            bptree::remove_index_file(dir_path, keyspace)?;
+        }
     }
+    Ok(())
 }
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use crossbeam_skiplist::SkipMap;
+use parking_lot::Mutex;
+
+use crate::{data::record::RecordPos, error::Result};
+
+use super::{
+    view::{CursorSnapshot, KeyRange, RangeSeek, Views},
+    Index, IndexSnapshot,
+};
+
+/// 基于无锁跳表的索引，读操作无需加锁
+pub(crate) struct SkipList {
+    map: Arc<SkipMap<Vec<u8>, RecordPos>>,
+    /// 串行化写操作，保证返回的旧位置、`compare_and_put`及视图记录的原子性
+    write_lock: Mutex<()>,
+    views: Views,
+}
+
+impl SkipList {
+    pub(crate) fn new() -> Self {
+        Self {
+            map: Arc::new(SkipMap::new()),
+            write_lock: Mutex::new(()),
+            views: Views::default(),
+        }
+    }
+}
+
+impl Index for SkipList {
+    fn put(&self, key: Vec<u8>, value: RecordPos) -> Result<Option<RecordPos>> {
+        let _guard = self.write_lock.lock();
+        let old = self.map.get(&key).map(|entry| *entry.value());
+        // 读操作不加锁，需在修改之前记录
+        self.views.record(&key, || old);
+        self.map.insert(key, value);
+        Ok(old)
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<RecordPos>> {
+        Ok(self.map.get(key).map(|entry| *entry.value()))
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<Option<RecordPos>> {
+        let _guard = self.write_lock.lock();
+        self.views
+            .record(key, || self.map.get(key).map(|entry| *entry.value()));
+        Ok(self.map.remove(key).map(|entry| *entry.value()))
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.map.len())
+    }
+
+    fn compare_and_put(&self, key: &[u8], expected: RecordPos, value: RecordPos) -> Result<bool> {
+        let _guard = self.write_lock.lock();
+        match self.map.get(key) {
+            Some(entry) if *entry.value() == expected => {
+                self.views.record(key, || Some(expected));
+                self.map.insert(key.to_vec(), value);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn clear(&self) -> Result<()> {
+        let _guard = self.write_lock.lock();
+        if !self.views.is_empty() {
+            for entry in self.map.iter() {
+                self.views.record(entry.key(), || Some(*entry.value()));
+            }
+        }
+        self.map.clear();
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn IndexSnapshot>> {
+        let undo = {
+            let _guard = self.write_lock.lock();
+            self.views.register()
+        };
+        Ok(Box::new(CursorSnapshot::new(self.map.clone(), undo)))
+    }
+}
+
+impl RangeSeek for SkipMap<Vec<u8>, RecordPos> {
+    fn seek_in(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        accept: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Option<(Vec<u8>, RecordPos)> {
+        let mut iter = self.range::<[u8], _>(range);
+        let found = if reverse {
+            iter.rfind(|entry| accept(entry.key()))
+        } else {
+            iter.find(|entry| accept(entry.key()))
+        };
+        found.map(|entry| (entry.key().clone(), *entry.value()))
+    }
+}
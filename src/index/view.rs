@@ -0,0 +1,315 @@
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    ops::{Bound, RangeBounds},
+    sync::{Arc, Weak},
+};
+
+use parking_lot::{Mutex, RwLock};
+
+use crate::{
+    config::IteratorConfig, data::record::RecordPos, error::Result, iterator::IndexIterator,
+};
+
+use super::IndexSnapshot;
+
+pub(crate) type KeyRange<'a> = (Bound<&'a [u8]>, Bound<&'a [u8]>);
+
+/// 有序索引按范围定位 key 的能力，惰性迭代器每次只定位下一个 key
+pub(crate) trait RangeSeek: Send + Sync + 'static {
+    /// 查找`range`内首个（`reverse`时为最后一个）满足`accept`的 key 及其位置
+    fn seek_in(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        accept: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Option<(Vec<u8>, RecordPos)>;
+}
+
+/// 视图创建后被修改的 key 及其在视图创建时的位置，`None`表示当时不存在
+#[derive(Default)]
+pub(crate) struct UndoLog {
+    entries: RwLock<BTreeMap<Vec<u8>, Option<RecordPos>>>,
+}
+
+impl UndoLog {
+    pub(crate) fn contains(&self, key: &[u8]) -> bool {
+        self.entries.read().contains_key(key)
+    }
+
+    /// 视图创建时 key 的位置，未被修改过时返回`None`
+    pub(crate) fn get(&self, key: &[u8]) -> Option<Option<RecordPos>> {
+        self.entries.read().get(key).copied()
+    }
+
+    /// `range`内全部在视图创建时存在的 key 及其位置
+    pub(crate) fn collect_in(&self, range: KeyRange) -> Vec<(Vec<u8>, RecordPos)> {
+        if is_empty_range(range) {
+            return Vec::new();
+        }
+        let entries = self.entries.read();
+        entries
+            .range::<[u8], _>(range)
+            .filter_map(|(key, pos)| Some((key.clone(), (*pos)?)))
+            .collect()
+    }
+
+    /// 查找`range`内首个（`reverse`时为最后一个）在视图创建时存在的 key
+    fn seek_in(&self, range: KeyRange, reverse: bool) -> Option<(Vec<u8>, RecordPos)> {
+        let entries = self.entries.read();
+        let mut iter = entries.range::<[u8], _>(range);
+        let found = if reverse {
+            iter.rev().find_map(|(key, pos)| Some((key, (*pos)?)))
+        } else {
+            iter.find_map(|(key, pos)| Some((key, (*pos)?)))
+        };
+        found.map(|(key, pos)| (key.clone(), pos))
+    }
+}
+
+/// 索引中存活的迭代器视图
+///
+/// 修改索引前需为每个视图记录被修改 key 的原位置，迭代器遍历最新的索引时
+/// 以此还原视图创建时的内容，只需保存迭代期间被修改的 key
+#[derive(Default)]
+pub(crate) struct Views {
+    logs: Mutex<Vec<Weak<UndoLog>>>,
+}
+
+impl Views {
+    /// 注册新的视图，调用方需持有索引的锁，保证此时没有进行中的修改
+    pub(crate) fn register(&self) -> Arc<UndoLog> {
+        let log = Arc::new(UndoLog::default());
+        let mut logs = self.logs.lock();
+        logs.retain(|l| l.strong_count() > 0);
+        logs.push(Arc::downgrade(&log));
+        log
+    }
+
+    /// 在修改 key 之前记录其当前位置，仅在存在视图时调用`old`
+    ///
+    /// 调用方需持有索引的写锁直至修改完成
+    pub(crate) fn record(&self, key: &[u8], old: impl FnOnce() -> Option<RecordPos>) {
+        let mut logs = self.logs.lock();
+        if logs.is_empty() {
+            return;
+        }
+
+        let old = old();
+        logs.retain(|log| match log.upgrade() {
+            Some(log) => {
+                log.entries.write().entry(key.to_vec()).or_insert(old);
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// 是否存在存活的视图
+    pub(crate) fn is_empty(&self) -> bool {
+        let mut logs = self.logs.lock();
+        logs.retain(|l| l.strong_count() > 0);
+        logs.is_empty()
+    }
+}
+
+/// 以`UndoLog`还原的有序索引快照，迭代器共享快照的`UndoLog`
+pub(crate) struct CursorSnapshot<S> {
+    source: Arc<S>,
+    undo: Arc<UndoLog>,
+}
+
+impl<S: RangeSeek> CursorSnapshot<S> {
+    /// 创建快照，`undo`需在持有索引的锁时注册
+    pub(crate) fn new(source: Arc<S>, undo: Arc<UndoLog>) -> Self {
+        Self { source, undo }
+    }
+}
+
+impl<S: RangeSeek> IndexSnapshot for CursorSnapshot<S> {
+    fn get(&self, key: &[u8]) -> Result<Option<RecordPos>> {
+        // 先读取索引再检查`UndoLog`，期间被修改的 key 必然已被记录
+        let range = (Bound::Included(key), Bound::Included(key));
+        let live = self.source.seek_in(range, false, &mut |_| true);
+        Ok(match self.undo.get(key) {
+            Some(old) => old,
+            None => live.map(|(_, pos)| pos),
+        })
+    }
+
+    fn iterator(&self, config: IteratorConfig) -> Result<Box<dyn IndexIterator>> {
+        Ok(Box::new(CursorIterator::new(
+            self.source.clone(),
+            self.undo.clone(),
+            &config,
+        )))
+    }
+}
+
+/// 基于游标的惰性迭代器，每次`next`只在索引中定位下一个 key
+///
+/// 迭代期间被修改的 key 从`UndoLog`中读取，保证迭代结果为迭代器创建时的一致视图
+pub(crate) struct CursorIterator<S> {
+    source: Arc<S>,
+    undo: Arc<UndoLog>,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    reverse: bool,
+    /// 下一次定位的起始边界，正向时为下界，反向时为上界
+    start: Bound<Vec<u8>>,
+}
+
+impl<S: RangeSeek> CursorIterator<S> {
+    fn new(source: Arc<S>, undo: Arc<UndoLog>, config: &IteratorConfig) -> Self {
+        let (lower, upper) = key_range(config);
+        let start = if config.reverse {
+            upper.clone()
+        } else {
+            lower.clone()
+        };
+        Self {
+            source,
+            undo,
+            lower,
+            upper,
+            reverse: config.reverse,
+            start,
+        }
+    }
+}
+
+impl<S: RangeSeek> IndexIterator for CursorIterator<S> {
+    fn rewind(&mut self) {
+        self.start = if self.reverse {
+            self.upper.clone()
+        } else {
+            self.lower.clone()
+        };
+    }
+
+    fn seek(&mut self, key: Vec<u8>) {
+        self.start = Bound::Included(key);
+    }
+
+    fn next(&mut self) -> Result<Option<(Vec<u8>, RecordPos)>> {
+        let range = if self.reverse {
+            (as_ref(&self.lower), min_upper(&self.upper, &self.start))
+        } else {
+            (max_lower(&self.lower, &self.start), as_ref(&self.upper))
+        };
+        if is_empty_range(range) {
+            return Ok(None);
+        }
+
+        // 先读取索引再检查`UndoLog`，期间被修改的 key 必然已被记录
+        let undo = &self.undo;
+        let live = self
+            .source
+            .seek_in(range, self.reverse, &mut |key| !undo.contains(key));
+        let old = undo.seek_in(range, self.reverse);
+        let item = match (live, old) {
+            (Some(live), Some(old)) => {
+                let old_first = match old.0.cmp(&live.0) {
+                    Ordering::Less => !self.reverse,
+                    Ordering::Greater => self.reverse,
+                    Ordering::Equal => true,
+                };
+                if old_first {
+                    old
+                } else {
+                    live
+                }
+            }
+            (live, old) => match live.or(old) {
+                Some(item) => item,
+                None => return Ok(None),
+            },
+        };
+
+        self.start = Bound::Excluded(item.0.clone());
+        Ok(Some(item))
+    }
+}
+
+/// 迭代的 key 范围，即`prefix`与上下界的交集
+pub(crate) fn key_range(config: &IteratorConfig) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let (prefix_lower, prefix_upper) = if config.prefix.is_empty() {
+        (Bound::Unbounded, Bound::Unbounded)
+    } else {
+        (
+            Bound::Included(config.prefix.clone()),
+            prefix_upper_bound(&config.prefix),
+        )
+    };
+    (
+        max_lower(&prefix_lower, &config.lower_bound).map(<[u8]>::to_vec),
+        min_upper(&prefix_upper, &config.upper_bound).map(<[u8]>::to_vec),
+    )
+}
+
+/// 以`prefix`为前缀的 key 的上界，即大于全部此类 key 的最小值
+fn prefix_upper_bound(prefix: &[u8]) -> Bound<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Bound::Excluded(end);
+        }
+    }
+    Bound::Unbounded
+}
+
+#[inline]
+pub(crate) fn as_ref(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key),
+        Bound::Excluded(key) => Bound::Excluded(key),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// 两个下界中较严格的一个
+pub(crate) fn max_lower<'a>(a: &'a Bound<Vec<u8>>, b: &'a Bound<Vec<u8>>) -> Bound<&'a [u8]> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => as_ref(bound),
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            match x.cmp(y) {
+                Ordering::Greater => as_ref(a),
+                Ordering::Less => as_ref(b),
+                Ordering::Equal if matches!(a, Bound::Excluded(_)) => as_ref(a),
+                Ordering::Equal => as_ref(b),
+            }
+        }
+    }
+}
+
+/// 两个上界中较严格的一个
+pub(crate) fn min_upper<'a>(a: &'a Bound<Vec<u8>>, b: &'a Bound<Vec<u8>>) -> Bound<&'a [u8]> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => as_ref(bound),
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            match x.cmp(y) {
+                Ordering::Less => as_ref(a),
+                Ordering::Greater => as_ref(b),
+                Ordering::Equal if matches!(a, Bound::Excluded(_)) => as_ref(a),
+                Ordering::Equal => as_ref(b),
+            }
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn range_contains(range: KeyRange, key: &[u8]) -> bool {
+    RangeBounds::<[u8]>::contains(&range, key)
+}
+
+/// 范围内是否不存在任何 key，`BTreeMap::range`等在此时会panic
+pub(crate) fn is_empty_range((lower, upper): KeyRange) -> bool {
+    match (lower, upper) {
+        (Bound::Included(l), Bound::Included(u)) => l > u,
+        (Bound::Included(l) | Bound::Excluded(l), Bound::Excluded(u) | Bound::Included(u)) => {
+            l >= u
+        }
+        _ => false,
+    }
+}
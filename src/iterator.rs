@@ -1,61 +1,178 @@
-use std::sync::Arc;
-
 use bytes::Bytes;
-use parking_lot::RwLock;
 
-use crate::{config::IteratorConfig, data::record::RecordPos, error::Result, Engine};
+use crate::{
+    config::IteratorConfig, data::record::RecordPos, error::Result, index::Index,
+    snapshot::StoragePin, Engine,
+};
 
 pub(crate) trait IndexIterator: Sync + Send {
     fn rewind(&mut self);
 
     fn seek(&mut self, key: Vec<u8>);
 
-    fn next(&mut self) -> Option<(&Vec<u8>, &RecordPos)>;
+    fn next(&mut self) -> Result<Option<(Vec<u8>, RecordPos)>>;
+}
+
+/// 跳过已过期 key 的索引迭代器
+struct UnexpiredIterator {
+    inner: Box<dyn IndexIterator>,
 }
 
+impl IndexIterator for UnexpiredIterator {
+    fn rewind(&mut self) {
+        self.inner.rewind();
+    }
+
+    fn seek(&mut self, key: Vec<u8>) {
+        self.inner.seek(key);
+    }
+
+    fn next(&mut self) -> Result<Option<(Vec<u8>, RecordPos)>> {
+        loop {
+            match self.inner.next()? {
+                Some((_, pos)) if pos.is_expired() => continue,
+                item => return Ok(item),
+            }
+        }
+    }
+}
+
+/// 限制返回条数的索引迭代器，`rewind`或`seek`后重新计数
+struct LimitIterator {
+    inner: Box<dyn IndexIterator>,
+    limit: usize,
+    count: usize,
+}
+
+impl IndexIterator for LimitIterator {
+    fn rewind(&mut self) {
+        self.count = 0;
+        self.inner.rewind();
+    }
+
+    fn seek(&mut self, key: Vec<u8>) {
+        self.count = 0;
+        self.inner.seek(key);
+    }
+
+    fn next(&mut self) -> Result<Option<(Vec<u8>, RecordPos)>> {
+        if self.count >= self.limit {
+            return Ok(None);
+        }
+        self.count += 1;
+        self.inner.next()
+    }
+}
+
+/// 数据迭代器，读取失败时返回错误
 pub struct Iterator<'a> {
-    engine: &'a Engine,
-    index_iter: Arc<RwLock<Box<dyn IndexIterator>>>,
+    pin: StoragePin<'a>,
+    index_iter: Box<dyn IndexIterator>,
 }
 
-impl Iterator<'_> {
-    pub fn rewind(&self) {
-        self.index_iter.write().rewind();
+impl<'a> Iterator<'a> {
+    pub(crate) fn new(
+        pin: StoragePin<'a>,
+        index_iter: Box<dyn IndexIterator>,
+        limit: Option<usize>,
+    ) -> Self {
+        let mut index_iter: Box<dyn IndexIterator> =
+            Box::new(UnexpiredIterator { inner: index_iter });
+        if let Some(limit) = limit {
+            index_iter = Box::new(LimitIterator {
+                inner: index_iter,
+                limit,
+                count: 0,
+            });
+        }
+        Self { pin, index_iter }
+    }
+
+    pub fn rewind(&mut self) {
+        self.index_iter.rewind();
+    }
+
+    pub fn seek(&mut self, key: Vec<u8>) {
+        self.index_iter.seek(key)
     }
 
-    pub fn seek(&self, key: Vec<u8>) {
-        self.index_iter.write().seek(key)
+    /// 只获取下一个 key，不读取磁盘中的 value
+    pub fn next_key(&mut self) -> Option<Result<Vec<u8>>> {
+        self.index_iter
+            .next()
+            .transpose()
+            .map(|item| item.map(|(key, _)| key))
     }
+}
+
+impl std::iter::Iterator for Iterator<'_> {
+    type Item = Result<(Vec<u8>, Bytes)>;
 
-    pub fn next(&self) -> Option<(Vec<u8>, Bytes)> {
-        if let Some((key, pos)) = self.index_iter.write().next() {
-            return self
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, pos) = match self.index_iter.next() {
+            Ok(Some(item)) => item,
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(
+            self.pin
                 .engine
-                .read_value_from_pos(pos)
-                .map(|value| (key.clone(), value))
-                .ok();
-        }
-        None
+                .read_value_from_pos(&pos)
+                .map(|value| (key, value)),
+        )
     }
 }
 
 impl Engine {
     /// 获取迭代器
-    pub fn iter(&self, config: IteratorConfig) -> Iterator {
-        Iterator {
-            engine: self,
-            index_iter: Arc::new(RwLock::new(self.index.iterator(config))),
+    pub fn iter(&self, config: IteratorConfig) -> Result<Iterator<'_>> {
+        self.iter_in(self.index.as_ref(), config)
+    }
+
+    /// 获取以`prefix`为前缀的全部 key，只读取索引
+    pub fn list_keys<B: Into<Vec<u8>>>(&self, prefix: B) -> Result<Vec<Vec<u8>>> {
+        self.list_keys_in(self.index.as_ref(), prefix.into())
+    }
+
+    /// 获取`index`上的迭代器，`index`为默认或命名 keyspace 的索引
+    pub(crate) fn iter_in(
+        &self,
+        index: &dyn Index,
+        config: IteratorConfig,
+    ) -> Result<Iterator<'_>> {
+        // 持有活跃文件的读锁，避免迭代器只包含批量写入的部分数据
+        let _active_storage = self.active_storage.read();
+        // 先于索引迭代器持有引用，避免迭代期间合并删除被迭代的`Storage`
+        let pin = self.pin_storages();
+        let limit = config.limit;
+        let index_iter = index.iterator(config)?;
+        Ok(Iterator::new(pin, index_iter, limit))
+    }
+
+    /// 获取`index`中以`prefix`为前缀的全部 key
+    pub(crate) fn list_keys_in(&self, index: &dyn Index, prefix: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+        let mut iter = self.iter_in(
+            index,
+            IteratorConfig {
+                prefix,
+                ..Default::default()
+            },
+        )?;
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_key() {
+            keys.push(key?);
         }
+        Ok(keys)
     }
 
-    /// 对数据库中当中的所有数据执行函数操作，函数返回 false 时终止
-    pub fn fold<F>(&self, f: F) -> Result<()>
+    /// 对数据库中当中的所有数据执行函数操作，函数返回 false 时终止，读取失败时返回错误
+    pub fn fold<F>(&self, mut f: F) -> Result<()>
     where
         Self: Sized,
-        F: Fn(Vec<u8>, Bytes) -> bool,
+        F: FnMut(Vec<u8>, Bytes) -> bool,
     {
-        let iter = self.iter(IteratorConfig::default());
-        while let Some((key, value)) = iter.next() {
+        for item in self.iter(IteratorConfig::default())? {
+            let (key, value) = item?;
             if !f(key, value) {
                 break;
             }
@@ -63,3 +180,236 @@ impl Engine {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, ops::Bound, thread};
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        data::{
+            record::{key_with_seq, Record, NON_BATCH_SEQ},
+            storage::storage_name_from_gen,
+        },
+        error::KvError,
+        index::IndexType,
+        test_util::{config, key, INDEX_TYPES},
+    };
+
+    fn open(dir: &TempDir, index_type: IndexType) -> Engine {
+        Engine::new(Config {
+            storage_size: 1024,
+            index_type,
+            ..config(dir)
+        })
+        .unwrap()
+    }
+
+    /// 写入`key(0)`至`key(99)`
+    fn write_keys(engine: &Engine) {
+        for i in 0..100 {
+            engine.set(key(i), key(i)).unwrap();
+        }
+    }
+
+    fn collect_keys(iter: &mut Iterator) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_key() {
+            keys.push(key.unwrap());
+        }
+        keys
+    }
+
+    fn keys(range: impl IntoIterator<Item = usize>) -> Vec<Vec<u8>> {
+        range.into_iter().map(key).collect()
+    }
+
+    #[test]
+    fn bounds_and_reverse() {
+        for index_type in INDEX_TYPES {
+            let dir = TempDir::new().unwrap();
+            let engine = open(&dir, index_type);
+            write_keys(&engine);
+
+            let config = |reverse, lower, upper| IteratorConfig {
+                reverse,
+                lower_bound: lower,
+                upper_bound: upper,
+                ..Default::default()
+            };
+            let mut iter = engine
+                .iter(config(
+                    false,
+                    Bound::Included(key(10)),
+                    Bound::Excluded(key(20)),
+                ))
+                .unwrap();
+            assert_eq!(collect_keys(&mut iter), keys(10..20));
+
+            let mut iter = engine
+                .iter(config(
+                    true,
+                    Bound::Excluded(key(10)),
+                    Bound::Included(key(20)),
+                ))
+                .unwrap();
+            assert_eq!(collect_keys(&mut iter), keys((11..=20).rev()));
+
+            // 与`prefix`同时指定时取交集
+            let mut iter = engine
+                .iter(IteratorConfig {
+                    prefix: b"key-001".to_vec(),
+                    lower_bound: Bound::Included(key(15)),
+                    ..Default::default()
+                })
+                .unwrap();
+            assert_eq!(collect_keys(&mut iter), keys(15..20));
+
+            // 空范围
+            let mut iter = engine
+                .iter(config(
+                    false,
+                    Bound::Included(key(20)),
+                    Bound::Excluded(key(10)),
+                ))
+                .unwrap();
+            assert!(collect_keys(&mut iter).is_empty());
+        }
+    }
+
+    #[test]
+    fn seek_respects_bounds_and_restarts_limit() {
+        for index_type in INDEX_TYPES {
+            let dir = TempDir::new().unwrap();
+            let engine = open(&dir, index_type);
+            write_keys(&engine);
+
+            let bounded = |reverse, limit| IteratorConfig {
+                reverse,
+                lower_bound: Bound::Included(key(10)),
+                upper_bound: Bound::Excluded(key(20)),
+                limit,
+                ..Default::default()
+            };
+
+            // 正向时定位至不小于`key`的首个 key，超出上界时结束
+            let mut iter = engine.iter(bounded(false, None)).unwrap();
+            iter.seek(key(15));
+            assert_eq!(collect_keys(&mut iter), keys(15..20));
+            iter.seek(key(30));
+            assert!(iter.next_key().is_none());
+            iter.seek(key(0));
+            assert_eq!(collect_keys(&mut iter), keys(10..20));
+
+            // 反向时定位至不大于`key`的最后一个 key，低于下界时结束
+            let mut iter = engine.iter(bounded(true, None)).unwrap();
+            iter.seek(key(15));
+            assert_eq!(collect_keys(&mut iter), keys((10..=15).rev()));
+            iter.seek(key(5));
+            assert!(iter.next_key().is_none());
+            iter.seek(key(30));
+            assert_eq!(collect_keys(&mut iter), keys((10..20).rev()));
+
+            // `seek`及`rewind`后重新计数
+            let mut iter = engine.iter(bounded(false, Some(3))).unwrap();
+            assert_eq!(collect_keys(&mut iter), keys(10..13));
+            iter.seek(key(18));
+            assert_eq!(collect_keys(&mut iter), keys(18..20));
+            iter.seek(key(12));
+            assert_eq!(collect_keys(&mut iter), keys(12..15));
+            iter.rewind();
+            assert_eq!(collect_keys(&mut iter), keys(10..13));
+
+            let mut iter = engine.iter(bounded(true, Some(2))).unwrap();
+            iter.seek(key(15));
+            assert_eq!(collect_keys(&mut iter), keys([15, 14]));
+        }
+    }
+
+    #[test]
+    fn lazy_iteration_during_concurrent_merge() {
+        for index_type in INDEX_TYPES {
+            let dir = TempDir::new().unwrap();
+            let engine = open(&dir, index_type);
+            write_keys(&engine);
+            for i in (0..100).step_by(2) {
+                engine.delete(key(i)).unwrap();
+            }
+
+            let mut iter = engine.iter(IteratorConfig::default()).unwrap();
+            let (first_key, first_value) = iter.next().unwrap().unwrap();
+            assert_eq!(first_key, key(1));
+            assert_eq!(first_value, key(1));
+
+            // 迭代期间合并移动并删除旧`Storage`，迭代器仍读取创建时的数据
+            thread::scope(|s| {
+                let merge = s.spawn(|| engine.merge().unwrap());
+                let mut items = vec![(first_key, first_value)];
+                for item in iter.by_ref() {
+                    items.push(item.unwrap());
+                }
+                merge.join().unwrap();
+
+                let expected = keys((1..100).step_by(2));
+                assert_eq!(items.len(), expected.len());
+                for ((key, value), expected) in items.iter().zip(&expected) {
+                    assert_eq!(key, expected);
+                    assert_eq!(value, expected.as_slice());
+                }
+            });
+
+            iter.rewind();
+            assert_eq!(collect_keys(&mut iter), keys((1..100).step_by(2)));
+            drop(iter);
+            assert_eq!(engine.list_keys("key-").unwrap(), keys((1..100).step_by(2)));
+        }
+    }
+
+    #[test]
+    fn read_errors_are_returned_instead_of_panicking() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir, IndexType::BTree);
+        write_keys(&engine);
+
+        // 修改gen 0中第4条`Record`的crc
+        let record_len = Record::new_set(key_with_seq(&key(0), NON_BATCH_SEQ), key(0))
+            .encode()
+            .unwrap()
+            .len();
+        let path = dir.path().join(storage_name_from_gen(0));
+        let mut data = fs::read(&path).unwrap();
+        data[record_len * 4 - 1] ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        let items = engine
+            .iter(IteratorConfig::default())
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(items.len(), 100);
+        for (i, item) in items.into_iter().enumerate() {
+            match item {
+                Err(KvError::InvalidCrc) if i == 3 => {}
+                Ok((k, v)) if i != 3 => {
+                    assert_eq!(k, key(i));
+                    assert_eq!(v, key(i));
+                }
+                _ => panic!("unexpected item at {}", i),
+            }
+        }
+
+        let mut visited = 0;
+        let res = engine.fold(|_, _| {
+            visited += 1;
+            true
+        });
+        assert!(matches!(res, Err(KvError::InvalidCrc)));
+        assert_eq!(visited, 3);
+
+        // 只读取索引时不受影响
+        let mut iter = engine.iter(IteratorConfig::default()).unwrap();
+        assert_eq!(collect_keys(&mut iter).len(), 100);
+    }
+}
@@ -0,0 +1,541 @@
+use std::{collections::HashMap, fs, io::ErrorKind, path::Path, sync::Arc, time::Duration};
+
+use bytes::{Buf, BufMut, Bytes};
+use prost::{
+    decode_length_delimiter, encode_length_delimiter,
+    encoding::{decode_varint, encode_varint},
+};
+
+use crate::{
+    config::IteratorConfig,
+    data::record::{key_with_seq, now_millis, Record, DEFAULT_KEYSPACE, NON_BATCH_SEQ},
+    error::{KvError, Result},
+    index::{new_index, remove_index, remove_unused_indexes, Index, IndexType},
+    iterator::Iterator,
+    util::write_file_atomically,
+    Engine,
+};
+
+const KEYSPACE_FILE_NAME: &str = "keyspaces";
+
+/// 按 keyspace ID 查找索引，包括默认 keyspace
+pub(crate) type IndexesById<'a> = HashMap<u32, &'a dyn Index>;
+
+/// keyspace 当前的ID及索引，清空后替换为新的ID及空索引
+pub(crate) struct KeyspaceState {
+    pub(crate) id: u32,
+    pub(crate) index: Box<dyn Index>,
+}
+
+/// 全部命名 keyspace，名称与ID的对应关系持久化于数据目录中
+///
+/// 已删除或清空的 keyspace 的ID不再复用，其`Record`在重启时被忽略，并在合并时被回收
+pub(crate) struct Keyspaces {
+    states: HashMap<String, Arc<KeyspaceState>>,
+    /// 下一个可分配的ID
+    next_id: u32,
+}
+
+impl Keyspaces {
+    /// 读取数据目录中的全部 keyspace 并打开其索引，同时删除不再使用的持久化索引文件
+    pub(crate) fn open(dir_path: &Path, index_type: IndexType) -> Result<Self> {
+        let (next_id, ids) = load_keyspace_file(dir_path)?;
+
+        let mut in_use = ids.iter().map(|(_, id)| *id).collect::<Vec<_>>();
+        in_use.push(DEFAULT_KEYSPACE);
+        remove_unused_indexes(index_type, dir_path, &in_use)?;
+
+        let states = ids
+            .into_iter()
+            .map(|(name, id)| {
+                let index = new_index(index_type, dir_path, id)?;
+                Ok((name, Arc::new(KeyspaceState { id, index })))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { states, next_id })
+    }
+
+    /// 全部 keyspace 的当前状态，不包括默认 keyspace
+    pub(crate) fn states(&self) -> Vec<Arc<KeyspaceState>> {
+        self.states.values().cloned().collect()
+    }
+
+    /// 以新分配的ID创建空的 keyspace
+    fn new_state(&mut self, dir_path: &Path, index_type: IndexType) -> Result<Arc<KeyspaceState>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let index = new_index(index_type, dir_path, id)?;
+        Ok(Arc::new(KeyspaceState { id, index }))
+    }
+
+    /// 持久化`states`后替换当前的全部 keyspace
+    fn update(
+        &mut self,
+        dir_path: &Path,
+        states: HashMap<String, Arc<KeyspaceState>>,
+    ) -> Result<()> {
+        write_keyspace_file(dir_path, self.next_id, &states)?;
+        self.states = states;
+        Ok(())
+    }
+}
+
+/// 按ID查找`states`及默认 keyspace 的索引
+pub(crate) fn indexes_by_id<'a>(
+    default: &'a dyn Index,
+    states: &'a [Arc<KeyspaceState>],
+) -> IndexesById<'a> {
+    let mut indexes = states
+        .iter()
+        .map(|state| (state.id, state.index.as_ref()))
+        .collect::<IndexesById>();
+    indexes.insert(DEFAULT_KEYSPACE, default);
+    indexes
+}
+
+/// | next id | id     | name size | name | ... | crc |
+/// | ------- | ------ | --------- | ---- | --- | --- |
+/// | 1 ~ 10  | 1 ~ 10 | 1 ~ 5     | dyn  | dyn | 4   |
+fn write_keyspace_file(
+    dir_path: &Path,
+    next_id: u32,
+    states: &HashMap<String, Arc<KeyspaceState>>,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    encode_varint(next_id as u64, &mut buf);
+    for (name, state) in states {
+        encode_varint(state.id as u64, &mut buf);
+        encode_length_delimiter(name.len(), &mut buf)?;
+        buf.extend_from_slice(name.as_bytes());
+    }
+    buf.put_u32(crc32fast::hash(&buf));
+    write_file_atomically(dir_path, KEYSPACE_FILE_NAME, &buf)
+}
+
+/// 读取下一个可分配的ID及全部 keyspace 的名称与ID
+fn load_keyspace_file(dir_path: &Path) -> Result<(u32, Vec<(String, u32)>)> {
+    let buf = match fs::read(dir_path.join(KEYSPACE_FILE_NAME)) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((DEFAULT_KEYSPACE + 1, Vec::new())),
+        Err(e) => return Err(e.into()),
+    };
+    if buf.len() < 4 {
+        return Err(KvError::ReadEOF);
+    }
+
+    let (mut content, mut crc) = buf.split_at(buf.len() - 4);
+    if crc32fast::hash(content) != crc.get_u32() {
+        return Err(KvError::InvalidCrc);
+    }
+
+    let next_id = decode_varint(&mut content)? as u32;
+    let mut ids = Vec::new();
+    while content.has_remaining() {
+        let id = decode_varint(&mut content)? as u32;
+        let name_size = decode_length_delimiter(&mut content)?;
+        if content.remaining() < name_size {
+            return Err(KvError::ReadEOF);
+        }
+        let name = String::from_utf8(content[..name_size].to_vec())
+            .map_err(|_| KvError::InvalidKeyspaceName)?;
+        content.advance(name_size);
+        ids.push((name, id));
+    }
+    Ok((next_id, ids))
+}
+
+/// 同一 Engine 中独立的命名空间，拥有独立的索引，与默认 keyspace 共享`Storage`
+///
+/// 以名称指向 keyspace，删除后的操作返回`KvError::KeyspaceNotFound`
+pub struct Keyspace<'a> {
+    engine: &'a Engine,
+    name: String,
+}
+
+impl Engine {
+    /// 获取名为`name`的 keyspace，不存在时创建
+    pub fn keyspace(&self, name: &str) -> Result<Keyspace<'_>> {
+        if name.is_empty() {
+            return Err(KvError::InvalidKeyspaceName);
+        }
+
+        if !self.keyspaces.read().states.contains_key(name) {
+            // 持有活跃文件的写锁，新的索引需与其他索引的检查点一致
+            let active_storage = self.active_storage.write();
+            {
+                let mut keyspaces = self.keyspaces.write();
+                if !keyspaces.states.contains_key(name) {
+                    let mut states = keyspaces.states.clone();
+                    let state =
+                        keyspaces.new_state(&self.config.dir_path, self.config.index_type)?;
+                    states.insert(name.to_string(), state);
+                    keyspaces.update(&self.config.dir_path, states)?;
+                }
+            }
+            self.save_checkpoint(&active_storage)?;
+        }
+
+        Ok(Keyspace {
+            engine: self,
+            name: name.to_string(),
+        })
+    }
+
+    /// 全部 keyspace 的名称，不包括默认 keyspace
+    pub fn keyspace_names(&self) -> Vec<String> {
+        let mut names = self
+            .keyspaces
+            .read()
+            .states
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+
+    /// 名为`name`的 keyspace 当前的状态，已被删除时返回错误
+    fn keyspace_state(&self, name: &str) -> Result<Arc<KeyspaceState>> {
+        self.keyspaces
+            .read()
+            .states
+            .get(name)
+            .cloned()
+            .ok_or(KvError::KeyspaceNotFound)
+    }
+
+    /// 存储 key，value 数据至`keyspace`，`None`表示默认 keyspace，`ttl`为`None`时永不过期
+    pub(crate) fn set_in(
+        &self,
+        keyspace: Option<&str>,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        if key.is_empty() {
+            return Err(KvError::InvalidKey);
+        }
+
+        // 持有活跃文件的写锁后再获取索引，避免写入已被清空的 keyspace
+        let mut active_storage = self.active_storage.write();
+        let state = keyspace.map(|name| self.keyspace_state(name)).transpose()?;
+        let (id, index) = match &state {
+            Some(state) => (state.id, state.index.as_ref()),
+            None => (DEFAULT_KEYSPACE, self.index.as_ref()),
+        };
+        let seq_key = key_with_seq(&key, NON_BATCH_SEQ);
+        let record = match ttl {
+            Some(ttl) => {
+                let expire_at = now_millis().saturating_add(ttl.as_millis() as u64);
+                Record::new_expiring(seq_key, value, expire_at)
+            }
+            None => Record::new_set(seq_key, value),
+        }
+        .with_keyspace(id);
+        self.put_record(&mut active_storage, index, key, &record)
+    }
+
+    /// 删除`keyspace`中未过期的 key，`None`表示默认 keyspace
+    pub(crate) fn delete_in(&self, keyspace: Option<&str>, key: Vec<u8>) -> Result<()> {
+        if key.is_empty() {
+            return Err(KvError::InvalidKey);
+        }
+
+        let mut active_storage = self.active_storage.write();
+        let state = keyspace.map(|name| self.keyspace_state(name)).transpose()?;
+        let (id, index) = match &state {
+            Some(state) => (state.id, state.index.as_ref()),
+            None => (DEFAULT_KEYSPACE, self.index.as_ref()),
+        };
+        // 先在索引中查找是否存在未过期的key
+        if index.get(&key)?.filter(|pos| !pos.is_expired()).is_none() {
+            return Err(KvError::InvalidKey);
+        }
+        self.remove_record(&mut active_storage, index, id, key)
+    }
+
+    /// 删除名为`name`的 keyspace 及其全部数据
+    pub fn drop_keyspace(&self, name: &str) -> Result<()> {
+        self.reset_keyspace(name, false)
+    }
+
+    /// 删除 keyspace，`recreate`为`true`时以新的ID及空索引替换
+    ///
+    /// 磁盘中的`Record`保持不变，其所属的ID不再被索引
+    fn reset_keyspace(&self, name: &str, recreate: bool) -> Result<()> {
+        let dir_path = &self.config.dir_path;
+        let active_storage = self.active_storage.write();
+        let old = {
+            let mut keyspaces = self.keyspaces.write();
+            let mut states = keyspaces.states.clone();
+            let Some(old) = states.remove(name) else {
+                return Err(KvError::KeyspaceNotFound);
+            };
+            if recreate {
+                let state = keyspaces.new_state(dir_path, self.config.index_type)?;
+                states.insert(name.to_string(), state);
+            }
+            keyspaces.update(dir_path, states)?;
+            old
+        };
+
+        // 旧ID的全部`Record`均可被合并回收
+        let mut iter = old.index.iterator(IteratorConfig::default())?;
+        while let Some((_, pos)) = iter.next()? {
+            self.add_reclaimable_size(pos.size as u64);
+        }
+        drop(iter);
+        let id = old.id;
+        drop(old);
+        remove_index(self.config.index_type, dir_path, id)?;
+
+        self.save_checkpoint(&active_storage)
+    }
+}
+
+impl<'a> Keyspace<'a> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// keyspace 当前的状态，已被删除时返回错误
+    fn state(&self) -> Result<Arc<KeyspaceState>> {
+        self.engine.keyspace_state(&self.name)
+    }
+
+    /// 存储 key，value 数据，其中 key 不为空
+    pub fn set<B: Into<Vec<u8>>>(&self, key: B, value: B) -> Result<()> {
+        self.engine
+            .set_in(Some(&self.name), key.into(), value.into(), None)
+    }
+
+    /// 存储 key，value 数据，并在`ttl`后过期
+    pub fn set_with_ttl<B: Into<Vec<u8>>>(&self, key: B, value: B, ttl: Duration) -> Result<()> {
+        self.engine
+            .set_in(Some(&self.name), key.into(), value.into(), Some(ttl))
+    }
+
+    /// 根据 key 获取对应的数据
+    pub fn get<B: Into<Vec<u8>>>(&self, key: B) -> Result<Bytes> {
+        let key = key.into();
+        self.engine.get_from(self.state()?.index.as_ref(), &key)
+    }
+
+    /// 根据 key 删除对应的数据
+    pub fn delete<B: Into<Vec<u8>>>(&self, key: B) -> Result<()> {
+        self.engine.delete_in(Some(&self.name), key.into())
+    }
+
+    /// keyspace 中 key 的数量
+    pub fn len(&self) -> Result<usize> {
+        self.state()?.index.len()
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// 获取 keyspace 上的迭代器
+    pub fn iter(&self, config: IteratorConfig) -> Result<Iterator<'a>> {
+        self.engine.iter_in(self.state()?.index.as_ref(), config)
+    }
+
+    /// 获取以`prefix`为前缀的全部 key，只读取索引
+    pub fn list_keys<B: Into<Vec<u8>>>(&self, prefix: B) -> Result<Vec<Vec<u8>>> {
+        self.engine
+            .list_keys_in(self.state()?.index.as_ref(), prefix.into())
+    }
+
+    /// 清空 keyspace 中的全部数据
+    pub fn clear(&self) -> Result<()> {
+        self.engine.reset_keyspace(&self.name, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        test_util::{config, key, remove_hints, INDEX_TYPES},
+    };
+
+    fn open(dir: &TempDir, index_type: IndexType) -> Engine {
+        Engine::new(Config {
+            storage_size: 1024,
+            index_type,
+            ..config(dir)
+        })
+        .unwrap()
+    }
+
+    fn value(name: &str, i: usize) -> Vec<u8> {
+        format!("{}-{}", name, i).into_bytes()
+    }
+
+    /// 在默认 keyspace 及`a`、`b`中写入相同的 key 及不同的 value
+    fn write_keyspaces(engine: &Engine) {
+        let a = engine.keyspace("a").unwrap();
+        let b = engine.keyspace("b").unwrap();
+        for i in 0..50 {
+            engine.set(key(i), value("default", i)).unwrap();
+            a.set(key(i), value("a", i)).unwrap();
+            b.set(key(i), value("b", i)).unwrap();
+        }
+        // 各 keyspace 中的覆盖及删除互不影响
+        for i in (0..50).step_by(2) {
+            a.set(key(i), value("a2", i)).unwrap();
+            b.delete(key(i)).unwrap();
+        }
+    }
+
+    fn assert_keyspaces(engine: &Engine) {
+        assert_eq!(engine.keyspace_names(), ["a", "b"]);
+        let a = engine.keyspace("a").unwrap();
+        let b = engine.keyspace("b").unwrap();
+        for i in 0..50 {
+            assert_eq!(engine.get(key(i)).unwrap(), value("default", i));
+            let expected = if i % 2 == 0 {
+                value("a2", i)
+            } else {
+                value("a", i)
+            };
+            assert_eq!(a.get(key(i)).unwrap(), expected);
+            if i % 2 == 0 {
+                assert!(matches!(b.get(key(i)), Err(KvError::InvalidKey)));
+            } else {
+                assert_eq!(b.get(key(i)).unwrap(), value("b", i));
+            }
+        }
+        assert_eq!(engine.stat().unwrap().key_num, 50 + 50 + 25);
+        assert_eq!(a.len().unwrap(), 50);
+        assert_eq!(b.len().unwrap(), 25);
+    }
+
+    #[test]
+    fn keyspaces_are_isolated_and_survive_reopen() {
+        for index_type in INDEX_TYPES {
+            let dir = TempDir::new().unwrap();
+            let engine = open(&dir, index_type);
+            write_keyspaces(&engine);
+            assert_keyspaces(&engine);
+            engine.close().unwrap();
+
+            let engine = open(&dir, index_type);
+            assert_keyspaces(&engine);
+            engine.close().unwrap();
+
+            // 从`Storage`重新构建索引时，`Record`按其 keyspace ID 归入对应的索引
+            remove_hints(&dir);
+            let engine = open(&dir, index_type);
+            assert_keyspaces(&engine);
+        }
+    }
+
+    #[test]
+    fn drop_and_clear_leave_other_keyspaces_untouched() {
+        for index_type in INDEX_TYPES {
+            let dir = TempDir::new().unwrap();
+            let engine = open(&dir, index_type);
+            write_keyspaces(&engine);
+            let c = engine.keyspace("c").unwrap();
+            c.set("k", "v").unwrap();
+
+            engine.drop_keyspace("c").unwrap();
+            assert!(matches!(c.get("k"), Err(KvError::KeyspaceNotFound)));
+            assert!(matches!(c.set("k", "v"), Err(KvError::KeyspaceNotFound)));
+            assert!(matches!(
+                engine.drop_keyspace("c"),
+                Err(KvError::KeyspaceNotFound)
+            ));
+            assert_keyspaces(&engine);
+
+            let d = engine.keyspace("d").unwrap();
+            d.set("k", "v").unwrap();
+            d.clear().unwrap();
+            assert!(d.is_empty().unwrap());
+            d.set("k2", "v2").unwrap();
+            engine.drop_keyspace("d").unwrap();
+            drop(engine.keyspace("d").unwrap());
+            engine.close().unwrap();
+
+            // 重启后已删除或清空的数据不再出现，同名的新 keyspace 为空
+            for _ in 0..2 {
+                let engine = open(&dir, index_type);
+                assert_eq!(engine.keyspace_names(), ["a", "b", "d"]);
+                assert!(engine.keyspace("d").unwrap().is_empty().unwrap());
+                engine.drop_keyspace("d").unwrap();
+                assert_keyspaces(&engine);
+                drop(engine.keyspace("d").unwrap());
+                engine.close().unwrap();
+                remove_hints(&dir);
+            }
+        }
+    }
+
+    #[test]
+    fn merge_rewrites_keyspace_records() {
+        for index_type in INDEX_TYPES {
+            let dir = TempDir::new().unwrap();
+            let engine = open(&dir, index_type);
+            write_keyspaces(&engine);
+            engine.keyspace("dropped").unwrap().set("k", "v").unwrap();
+            engine.drop_keyspace("dropped").unwrap();
+
+            engine.merge().unwrap();
+            assert_keyspaces(&engine);
+            engine.close().unwrap();
+
+            let engine = open(&dir, index_type);
+            assert_keyspaces(&engine);
+            engine.close().unwrap();
+
+            remove_hints(&dir);
+            let engine = open(&dir, index_type);
+            assert_keyspaces(&engine);
+        }
+    }
+
+    #[test]
+    fn iteration_stays_within_keyspace() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir, IndexType::BTree);
+        write_keyspaces(&engine);
+        engine.set("key-x", "default").unwrap();
+
+        let b = engine.keyspace("b").unwrap();
+        let keys = b.list_keys("key-").unwrap();
+        assert_eq!(keys, (1..50).step_by(2).map(key).collect::<Vec<_>>());
+        assert_eq!(
+            b.list_keys("key-001").unwrap(),
+            [key(11), key(13), key(15), key(17), key(19)]
+        );
+
+        let items = b
+            .iter(IteratorConfig {
+                reverse: true,
+                limit: Some(3),
+                ..Default::default()
+            })
+            .unwrap()
+            .map(|item| item.unwrap())
+            .collect::<Vec<_>>();
+        let expected = [49, 47, 45].map(|i| (key(i), value("b", i)));
+        assert_eq!(items.len(), expected.len());
+        for ((k, v), (ek, ev)) in items.iter().zip(&expected) {
+            assert_eq!(k, ek);
+            assert_eq!(v, ev.as_slice());
+        }
+
+        // 删除后的 keyspace 无法再获取迭代器
+        engine.drop_keyspace("b").unwrap();
+        assert!(matches!(b.list_keys(""), Err(KvError::KeyspaceNotFound)));
+        assert!(matches!(
+            b.iter(IteratorConfig::default()),
+            Err(KvError::KeyspaceNotFound)
+        ));
+        assert_eq!(engine.list_keys("key-").unwrap().len(), 51);
+    }
+}
@@ -6,8 +6,23 @@ mod error;
 mod fio;
 mod index;
 mod iterator;
+mod keyspace;
+mod merge;
+mod snapshot;
+#[cfg(test)]
+mod test_util;
+mod transaction;
+mod util;
 
-pub use config::Config;
-pub use engine::Engine;
-pub use error::Result;
+pub use batch::WriteBatch;
+pub use config::{BatchConfig, Config, IteratorConfig, MergeOperator, RecoveryMode};
+pub use engine::{Engine, Stat};
+pub use error::{KvError, Result};
+pub use fio::IOType;
+#[cfg(feature = "bench")]
+pub use index::BenchIndex;
+pub use index::IndexType;
 pub use iterator::Iterator;
+pub use keyspace::Keyspace;
+pub use snapshot::Snapshot;
+pub use transaction::Transaction;
@@ -0,0 +1,720 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    config::{IteratorConfig, RecoveryMode},
+    data::{
+        hint::{hint_name_from_gen, is_hint_file, Hint},
+        record::{
+            key_with_seq, parse_seq_key, Record, RecordPos, RecordType, NON_BATCH_SEQ, NO_EXPIRE,
+        },
+        storage::{is_storage_file, storage_name_from_gen, Storage},
+    },
+    error::{KvError, Result},
+    fio::IOType,
+    keyspace::{indexes_by_id, IndexesById},
+    util::{sync_dir, write_file_atomically},
+    Engine,
+};
+
+const MERGE_DIR_NAME: &str = "merge";
+const MERGE_FINISHED_FILE_NAME: &str = "merge-finished";
+const RETIRED_FILE_NAME: &str = "retired";
+
+impl Engine {
+    /// 将旧`Storage`中的有效数据重写至新的`Storage`，清理被覆盖或删除的`Record`
+    ///
+    /// 合并期间仅在轮转活跃文件时短暂阻塞写入
+    pub fn merge(&self) -> Result<()> {
+        let Some(_guard) = self.merge_lock.try_lock() else {
+            return Err(KvError::MergeInProgress);
+        };
+
+        // 轮转活跃文件，并在新活跃文件之前为合并结果预留gen
+        let (mut merge_gens, mut merging) = {
+            let mut active_storage = self.active_storage.write();
+            let mut gens = self
+                .older_storages
+                .read()
+                .keys()
+                .copied()
+                .collect::<Vec<_>>();
+            if gens.is_empty() && active_storage.get_offset() == 0 {
+                return Ok(());
+            }
+            gens.push(active_storage.gen);
+            *self.merging_gen.lock() = Some(active_storage.gen);
+            let merging = MergingGuard {
+                engine: self,
+                merge_path: None,
+            };
+
+            let new_gen = active_storage.gen + gens.len() as u32 + 1;
+            self.rotate_active_storage(&mut active_storage, new_gen)?;
+            (gens, merging)
+        };
+        merge_gens.sort_unstable();
+        let first_merged_gen = merge_gens[merge_gens.len() - 1] + 1;
+        let max_merged_gen = first_merged_gen + merge_gens.len() as u32 - 1;
+
+        let dir_path = &self.config.dir_path;
+        let merge_path = merge_dir_path(dir_path);
+        if merge_path.is_dir() {
+            fs::remove_dir_all(&merge_path)?;
+        }
+        merging.merge_path = Some(merge_path.clone());
+        fs::create_dir_all(&merge_path)?;
+
+        // 重写索引仍指向的`Record`，已删除或清空的 keyspace 的`Record`均不再重写
+        let states = self.keyspaces.read().states();
+        let indexes = indexes_by_id(self.index.as_ref(), &states);
+        let mut writer = MergeWriter::new(&merge_path, first_merged_gen, max_merged_gen);
+        let mut moved = Vec::new();
+        // 已过期的写入不再重写，合并后从索引中移除
+        let mut expired = Vec::new();
+        let mut input_size = 0;
+        for gen in merge_gens.iter().copied() {
+            let gen_path = dir_path.join(storage_name_from_gen(gen));
+            let storage = Storage::new(gen_path.as_path(), self.config.io_type)?;
+            // 扫描至已知的数据末尾，而非在首个无法解析的header处停止
+            let data_size = self
+                .older_storages
+                .read()
+                .get(&gen)
+                .map_or(0, Storage::get_offset);
+
+            let mut offset = 0;
+            while offset < data_size {
+                let header = match storage.read_record_head_buf(offset) {
+                    Ok(h) if offset + h.encoded_len() as u64 <= data_size => h,
+                    Err(KvError::Io(e)) => return Err(KvError::Io(e)),
+                    // 无法解析的header或不完整的`Record`，只能从其后首个有效的`Record`继续
+                    r => {
+                        if self.config.recovery_mode == RecoveryMode::Strict {
+                            return Err(r.err().unwrap_or(KvError::ReadEOF));
+                        }
+                        let next = storage
+                            .find_valid_record(offset + 1, data_size)?
+                            .unwrap_or(data_size);
+                        tracing::warn!(
+                            "merge skips {} corrupted bytes in storage {} at offset {}",
+                            next - offset,
+                            gen,
+                            offset
+                        );
+                        offset = next;
+                        continue;
+                    }
+                };
+                let record_size = header.encoded_len() as u64;
+
+                if let RecordType::Normal | RecordType::Expiring | RecordType::MergeOperand =
+                    header.record_type
+                {
+                    let (keyspace, key) = match storage
+                        .read_key_from_header(offset, &header)
+                        .and_then(|(keyspace, key)| Ok((keyspace, parse_seq_key(key)?.0)))
+                    {
+                        Ok(item) => item,
+                        Err(KvError::Io(e)) => return Err(KvError::Io(e)),
+                        Err(e) if self.config.recovery_mode == RecoveryMode::Strict => {
+                            return Err(e)
+                        }
+                        Err(e) => {
+                            tracing::warn!(
+                                "merge skips record in storage {} at offset {}: {}",
+                                gen,
+                                offset,
+                                e
+                            );
+                            offset += record_size;
+                            continue;
+                        }
+                    };
+                    let pos = match indexes.get(&keyspace) {
+                        Some(index) => index.get(&key)?,
+                        None => None,
+                    };
+                    match pos {
+                        Some(pos) if pos.gen == gen && pos.offset == offset => {
+                            if pos.is_expired() {
+                                expired.push((keyspace, key, pos));
+                            } else {
+                                let record = self.rewrite_record(&storage, &key, &pos)?;
+                                let new_pos = writer.write(&record, self.config.storage_size)?;
+                                moved.push((keyspace, key, pos, new_pos));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                offset += record_size;
+            }
+            input_size += offset;
+        }
+        let output_size = writer.written_size;
+        let merged_gens = writer.finish()?;
+
+        // 索引仍指向的`Record`均需已被重写，否则删除旧文件将丢失数据
+        let scanned = moved
+            .iter()
+            .map(|(_, _, pos, _)| (pos.gen, pos.offset))
+            .chain(expired.iter().map(|(_, _, pos)| (pos.gen, pos.offset)))
+            .collect::<HashSet<_>>();
+        check_merged_positions(dir_path, &indexes, &merge_gens, &scanned)?;
+
+        // 写入合并完成标记，此后的步骤在重启时可以继续完成；标记及合并结果随合并目录一同落盘
+        write_file_atomically(
+            &merge_path,
+            MERGE_FINISHED_FILE_NAME,
+            first_merged_gen.to_string().as_bytes(),
+        )?;
+        // 已完成的合并在重启时继续，失败时不再删除合并目录
+        merging.merge_path = None;
+
+        // 将合并后的`Storage`及hint文件移入数据目录并打开
+        for gen in merged_gens.iter().copied() {
+            let name = storage_name_from_gen(gen);
+            fs::rename(merge_path.join(&name), dir_path.join(&name))?;
+            let name = hint_name_from_gen(gen);
+            fs::rename(merge_path.join(&name), dir_path.join(&name))?;
+        }
+        sync_dir(dir_path)?;
+        {
+            let mut older_storages = self.older_storages.write();
+            for gen in merged_gens.iter().copied() {
+                let gen_path = dir_path.join(storage_name_from_gen(gen));
+                older_storages.insert(gen, Storage::new(gen_path.as_path(), self.config.io_type)?);
+            }
+        }
+
+        // 更新期间被覆盖或删除的key保持不变
+        for (keyspace, key, pos, new_pos) in moved {
+            indexes[&keyspace].compare_and_put(&key, pos, new_pos)?;
+        }
+        {
+            // 持有活跃文件的写锁，期间没有其他写入修改索引
+            let active_storage = self.active_storage.write();
+            for (keyspace, key, pos) in expired {
+                let index = indexes[&keyspace];
+                if index.get(&key)? == Some(pos) {
+                    index.delete(&key)?;
+                }
+            }
+            // 持久化索引中的新位置需在删除旧文件前落盘
+            self.save_checkpoint(&active_storage)?;
+        }
+
+        // 移除并删除已合并的`Storage`，仍被快照引用的文件延迟删除
+        self.retire_storages(&merge_gens)?;
+        drop(merging);
+        fs::remove_dir_all(&merge_path)?;
+
+        // 扣除已被回收的字节数
+        let reclaimed_size = input_size.saturating_sub(output_size);
+        let _ = self
+            .reclaimable_size
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |size| {
+                Some(size.saturating_sub(reclaimed_size))
+            });
+
+        Ok(())
+    }
+}
+
+/// 合并结束时重置`merging_gen`，包括因错误提前返回的情况
+///
+/// `merge_path`不为`None`时合并尚未写入完成标记，其结果无法使用，一并删除合并目录
+struct MergingGuard<'a> {
+    engine: &'a Engine,
+    merge_path: Option<PathBuf>,
+}
+
+impl Drop for MergingGuard<'_> {
+    fn drop(&mut self) {
+        *self.engine.merging_gen.lock() = None;
+        if let Some(merge_path) = self.merge_path.take() {
+            match fs::remove_dir_all(&merge_path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    tracing::warn!("failed to remove merge directory: {}", e)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Engine {
+    /// 重写`pos`处的`Record`，合并操作数被合并为完整的 value
+    fn rewrite_record(&self, storage: &Storage, key: &[u8], pos: &RecordPos) -> Result<Record> {
+        let key = key_with_seq(key, NON_BATCH_SEQ);
+        let record = storage.read_record(pos.offset)?;
+        if !matches!(record.record_type, RecordType::MergeOperand) {
+            return Ok(Record { key, ..record });
+        }
+
+        let value = self.read_value_from_pos(pos)?.to_vec();
+        let rewritten = match pos.expire_at {
+            NO_EXPIRE => Record::new_set(key, value),
+            expire_at => Record::new_expiring(key, value, expire_at),
+        };
+        Ok(rewritten.with_keyspace(record.keyspace))
+    }
+}
+
+/// 将合并结果按`storage_size`分割写入合并目录
+struct MergeWriter<'a> {
+    merge_path: &'a Path,
+    next_gen: u32,
+    max_gen: u32,
+    current: Option<Storage>,
+    hint: Hint,
+    gens: Vec<u32>,
+    written_size: u64,
+}
+
+impl<'a> MergeWriter<'a> {
+    fn new(merge_path: &'a Path, first_gen: u32, max_gen: u32) -> Self {
+        Self {
+            merge_path,
+            next_gen: first_gen,
+            max_gen,
+            current: None,
+            hint: Hint::default(),
+            gens: Vec::new(),
+            written_size: 0,
+        }
+    }
+
+    fn write(&mut self, record: &Record, storage_size: u64) -> Result<RecordPos> {
+        let record_data = record.encode()?;
+
+        let need_rotate = match &self.current {
+            Some(s) => {
+                s.get_offset() > 0 && s.get_offset() + record_data.len() as u64 > storage_size
+            }
+            None => true,
+        };
+        if need_rotate {
+            self.finish_current()?;
+            if self.next_gen > self.max_gen {
+                return Err(KvError::MergeGenExhausted);
+            }
+
+            let gen_path = self.merge_path.join(storage_name_from_gen(self.next_gen));
+            self.current = Some(Storage::new(gen_path.as_path(), IOType::StandardFIO)?);
+            self.gens.push(self.next_gen);
+            self.next_gen += 1;
+        }
+
+        let storage = self.current.as_ref().unwrap();
+        let offset = storage.get_offset();
+        storage.write(&record_data)?;
+        let size = record_data.len() as u32;
+        self.hint.push(
+            record.record_type,
+            record.keyspace,
+            &record.key,
+            offset,
+            size,
+            record.expire_at(),
+        );
+        self.written_size += size as u64;
+        Ok(RecordPos {
+            gen: storage.gen,
+            offset,
+            size,
+            expire_at: record.expire_at(),
+        })
+    }
+
+    /// 持久化当前写入的`Storage`并写入其hint文件
+    fn finish_current(&mut self) -> Result<()> {
+        if let Some(s) = self.current.take() {
+            s.sync()?;
+            let hint_path = self.merge_path.join(hint_name_from_gen(s.gen));
+            self.hint.write_to(&hint_path, s.get_offset())?;
+            self.hint.clear();
+        }
+        Ok(())
+    }
+
+    /// 持久化并返回写入的全部gen
+    fn finish(mut self) -> Result<Vec<u32>> {
+        self.finish_current()?;
+        Ok(self.gens)
+    }
+}
+
+/// 确认索引中指向`merge_gens`的位置均在`scanned`中，即已被重写或将因过期而移除
+///
+/// 合并期间的写入只会指向新的活跃文件，未被扫描到的位置说明旧文件中的数据无法读取
+fn check_merged_positions(
+    dir_path: &Path,
+    indexes: &IndexesById,
+    merge_gens: &[u32],
+    scanned: &HashSet<(u32, u64)>,
+) -> Result<()> {
+    for index in indexes.values() {
+        let mut iter = index.iterator(IteratorConfig::default())?;
+        while let Some((_, pos)) = iter.next()? {
+            if merge_gens.contains(&pos.gen) && !scanned.contains(&(pos.gen, pos.offset)) {
+                return Err(KvError::CorruptedFile(
+                    dir_path.join(storage_name_from_gen(pos.gen)),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+fn merge_dir_path(dir_path: &Path) -> PathBuf {
+    dir_path.join(MERGE_DIR_NAME)
+}
+
+/// 记录存在已合并但尚未删除的`Storage`，gen 小于`first_merged_gen`的文件均已被合并
+pub(crate) fn write_retired_marker(dir_path: &Path, first_merged_gen: u32) -> Result<()> {
+    write_file_atomically(
+        dir_path,
+        RETIRED_FILE_NAME,
+        first_merged_gen.to_string().as_bytes(),
+    )
+}
+
+/// 已合并的`Storage`均已删除后移除标记，文件不存在时忽略
+pub(crate) fn remove_retired_marker(dir_path: &Path) -> Result<()> {
+    match fs::remove_file(dir_path.join(RETIRED_FILE_NAME)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// 删除 gen 小于`gen`的`Storage`及hint文件
+fn remove_files_before(dir_path: &Path, gen: u32) -> Result<()> {
+    for entry in fs::read_dir(dir_path)? {
+        let gen_path = entry?.path();
+        if gen_path.is_file() {
+            let file_gen = is_storage_file(gen_path.as_path()).or_else(|_| is_hint_file(&gen_path));
+            if file_gen.is_ok_and(|file_gen| file_gen < gen) {
+                fs::remove_file(&gen_path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 完成已写入合并完成标记的合并，未完成的合并结果将被丢弃，返回是否完成了合并
+///
+/// 上次退出前仍被快照引用而未能删除的已合并文件在此一并删除
+pub(crate) fn load_merge_files(dir_path: &Path) -> Result<bool> {
+    let retired_path = dir_path.join(RETIRED_FILE_NAME);
+    if retired_path.is_file() {
+        let first_merged_gen = fs::read_to_string(&retired_path)?
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| KvError::CorruptedFile(retired_path))?;
+        remove_files_before(dir_path, first_merged_gen)?;
+        remove_retired_marker(dir_path)?;
+    }
+
+    let merge_path = merge_dir_path(dir_path);
+    if !merge_path.is_dir() {
+        return Ok(false);
+    }
+
+    let finished_path = merge_path.join(MERGE_FINISHED_FILE_NAME);
+    if !finished_path.is_file() {
+        fs::remove_dir_all(&merge_path)?;
+        return Ok(false);
+    }
+    let first_merged_gen = fs::read_to_string(&finished_path)?
+        .trim()
+        .parse::<u32>()
+        .map_err(|_| KvError::CorruptedFile(finished_path))?;
+
+    // 移入合并后的`Storage`及hint文件
+    for entry in fs::read_dir(&merge_path)? {
+        let gen_path = entry?.path();
+        if is_storage_file(gen_path.as_path()).is_ok() || is_hint_file(gen_path.as_path()).is_ok() {
+            if let Some(name) = gen_path.file_name() {
+                fs::rename(&gen_path, dir_path.join(name))?;
+            }
+        }
+    }
+
+    sync_dir(dir_path)?;
+
+    // 删除已被合并的`Storage`及hint文件
+    remove_files_before(dir_path, first_merged_gen)?;
+
+    fs::remove_dir_all(&merge_path)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        index::IndexType,
+        test_util::{config, key, storages_size, INDEX_TYPES},
+    };
+
+    fn open(dir: &TempDir, index_type: IndexType) -> Engine {
+        Engine::new(Config {
+            storage_size: 4 * 1024,
+            index_type,
+            ..config(dir)
+        })
+        .unwrap()
+    }
+
+    /// 写入覆盖及删除交错的数据，返回最终应存在的 key 及其 value
+    fn write_data(engine: &Engine) -> Vec<(Vec<u8>, Vec<u8>)> {
+        for i in 0..200 {
+            engine
+                .set(key(i), format!("v1-{}", i).into_bytes())
+                .unwrap();
+        }
+        for i in (0..200).step_by(2) {
+            engine
+                .set(key(i), format!("v2-{}", i).into_bytes())
+                .unwrap();
+        }
+        for i in (0..200).step_by(3) {
+            engine.delete(key(i)).unwrap();
+        }
+        (0..200)
+            .filter(|i| i % 3 != 0)
+            .map(|i| {
+                let version = if i % 2 == 0 { "v2" } else { "v1" };
+                (key(i), format!("{}-{}", version, i).into_bytes())
+            })
+            .collect()
+    }
+
+    fn assert_data(engine: &Engine, expected: &[(Vec<u8>, Vec<u8>)]) {
+        for i in (0..200).step_by(3) {
+            assert!(matches!(engine.get(key(i)), Err(KvError::InvalidKey)));
+        }
+        for (key, value) in expected {
+            assert_eq!(engine.get(key.clone()).unwrap(), value.as_slice());
+        }
+        assert_eq!(engine.stat().unwrap().key_num, expected.len());
+    }
+
+    #[test]
+    fn merge_survives_restart() {
+        for index_type in INDEX_TYPES {
+            let dir = TempDir::new().unwrap();
+            let engine = open(&dir, index_type);
+            let expected = write_data(&engine);
+            let before = engine.stat().unwrap();
+            let size_before = storages_size(&dir);
+
+            engine.merge().unwrap();
+            assert_data(&engine, &expected);
+            assert!(engine.stat().unwrap().reclaimable_size < before.reclaimable_size);
+            assert!(storages_size(&dir) < size_before);
+
+            // 合并后的写入同样保留
+            engine.set(b"after".to_vec(), b"merge".to_vec()).unwrap();
+            engine.close().unwrap();
+
+            let engine = open(&dir, index_type);
+            assert_eq!(engine.get("after").unwrap(), "merge");
+            engine.delete("after").unwrap();
+            assert_data(&engine, &expected);
+        }
+    }
+
+    #[test]
+    fn deleted_keys_stay_deleted_without_hints() {
+        for index_type in INDEX_TYPES {
+            let dir = TempDir::new().unwrap();
+            let engine = open(&dir, index_type);
+            let expected = write_data(&engine);
+            engine.merge().unwrap();
+            drop(engine);
+
+            // 删除hint文件及持久化索引，从`Storage`重新构建索引
+            for entry in fs::read_dir(dir.path()).unwrap() {
+                let path = entry.unwrap().path();
+                if is_hint_file(&path).is_ok() || path.extension() == Some("index".as_ref()) {
+                    fs::remove_file(path).unwrap();
+                }
+            }
+            let engine = open(&dir, index_type);
+            assert_data(&engine, &expected);
+
+            // 再次合并及重启后不变
+            engine.merge().unwrap();
+            drop(engine);
+            assert_data(&open(&dir, index_type), &expected);
+        }
+    }
+
+    #[test]
+    fn retired_storages_left_by_crash_are_removed_on_restart() {
+        for index_type in INDEX_TYPES {
+            let dir = TempDir::new().unwrap();
+            let engine = open(&dir, index_type);
+            let expected = write_data(&engine);
+
+            // 快照释放前进程退出，被引用的已合并文件未能删除
+            std::mem::forget(engine.pin_storages());
+            engine.merge().unwrap();
+            drop(engine);
+            let marker = fs::read_to_string(dir.path().join(RETIRED_FILE_NAME)).unwrap();
+            let first_merged_gen = marker.parse::<u32>().unwrap();
+
+            // 退出时部分文件已被删除，包含删除记录的最后一个文件已不存在
+            let last_retired = dir.path().join(storage_name_from_gen(first_merged_gen - 1));
+            fs::remove_file(last_retired).unwrap();
+
+            let engine = open(&dir, index_type);
+            assert!(!dir.path().join(RETIRED_FILE_NAME).exists());
+            for entry in fs::read_dir(dir.path()).unwrap() {
+                let path = entry.unwrap().path();
+                let gen = is_storage_file(&path).or_else(|_| is_hint_file(&path));
+                assert!(gen.map_or(true, |gen| gen >= first_merged_gen));
+            }
+            assert_data(&engine, &expected);
+        }
+    }
+
+    #[test]
+    fn retired_marker_is_removed_after_release() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir, IndexType::BTree);
+        let expected = write_data(&engine);
+
+        let snapshot = engine.snapshot().unwrap();
+        engine.merge().unwrap();
+        assert!(dir.path().join(RETIRED_FILE_NAME).is_file());
+        assert_eq!(snapshot.get(key(1)).unwrap(), "v1-1");
+
+        drop(snapshot);
+        assert!(!dir.path().join(RETIRED_FILE_NAME).exists());
+        assert_data(&engine, &expected);
+    }
+
+    #[test]
+    fn unfinished_merge_is_discarded() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir, IndexType::BTree);
+        let expected = write_data(&engine);
+        drop(engine);
+
+        // 未写入合并完成标记的合并结果
+        let merge_path = merge_dir_path(dir.path());
+        fs::create_dir_all(&merge_path).unwrap();
+        fs::write(merge_path.join(storage_name_from_gen(1000)), b"partial").unwrap();
+
+        let engine = open(&dir, IndexType::BTree);
+        assert!(!merge_path.exists());
+        assert!(!dir.path().join(storage_name_from_gen(1000)).exists());
+        assert_data(&engine, &expected);
+    }
+
+    #[test]
+    fn corrupted_merge_finished_marker_names_the_file() {
+        let dir = TempDir::new().unwrap();
+        drop(open(&dir, IndexType::BTree));
+
+        let merge_path = merge_dir_path(dir.path());
+        fs::create_dir_all(&merge_path).unwrap();
+        fs::write(merge_path.join(MERGE_FINISHED_FILE_NAME), b"not a gen").unwrap();
+
+        let res = Engine::new(Config {
+            dir_path: dir.path().to_path_buf(),
+            ..Default::default()
+        });
+        match res {
+            Err(KvError::CorruptedFile(path)) => {
+                assert!(path.ends_with(MERGE_FINISHED_FILE_NAME))
+            }
+            _ => panic!("expected KvError::CorruptedFile"),
+        }
+    }
+
+    /// 将`gen`的`Storage`中`offset`处的字节改写为0，使其`Record`无法解析
+    fn corrupt_type_byte(dir: &TempDir, gen: u32, offset: u64) {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(dir.path().join(storage_name_from_gen(gen)))
+            .unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&[0]).unwrap();
+    }
+
+    fn open_with_mode(dir: &TempDir, recovery_mode: RecoveryMode) -> Engine {
+        Engine::new(Config {
+            storage_size: 4 * 1024,
+            recovery_mode,
+            ..config(dir)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn merge_handles_corrupted_record_by_recovery_mode() {
+        for recovery_mode in [RecoveryMode::Strict, RecoveryMode::Lenient] {
+            let dir = TempDir::new().unwrap();
+            let engine = open_with_mode(&dir, recovery_mode);
+            // 首条`Record`被覆盖后不再被索引引用，其后的数据仍需被合并
+            engine.set(key(0), b"stale".to_vec()).unwrap();
+            for i in 0..10 {
+                engine.set(key(i), format!("v-{}", i).into_bytes()).unwrap();
+            }
+            corrupt_type_byte(&dir, 0, 0);
+
+            let res = engine.merge();
+            let gen_path = dir.path().join(storage_name_from_gen(0));
+            if recovery_mode == RecoveryMode::Strict {
+                assert!(matches!(res, Err(KvError::ReadEOF)));
+                assert!(gen_path.is_file());
+            } else {
+                res.unwrap();
+                assert!(!gen_path.exists());
+            }
+            for i in 0..10 {
+                assert_eq!(engine.get(key(i)).unwrap(), format!("v-{}", i));
+            }
+        }
+    }
+
+    #[test]
+    fn merge_keeps_storage_still_referenced_by_index() {
+        let dir = TempDir::new().unwrap();
+        let engine = open_with_mode(&dir, RecoveryMode::Lenient);
+        for i in 0..10 {
+            engine.set(key(i), format!("v-{}", i).into_bytes()).unwrap();
+        }
+        // 索引仍指向无法解析的`Record`，旧文件不能被删除
+        corrupt_type_byte(&dir, 0, 0);
+
+        let gen_path = dir.path().join(storage_name_from_gen(0));
+        match engine.merge() {
+            Err(KvError::CorruptedFile(path)) => assert_eq!(path, gen_path),
+            _ => panic!("expected KvError::CorruptedFile"),
+        }
+        assert!(gen_path.is_file());
+        // 失败的合并不再影响之后的写入及合并
+        assert!(engine.merging_gen.lock().is_none());
+        assert!(!merge_dir_path(dir.path()).exists());
+        for i in 1..10 {
+            assert_eq!(engine.get(key(i)).unwrap(), format!("v-{}", i));
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::HashMap, mem, sync::Arc};
+
+use bytes::Bytes;
+
+use crate::{
+    config::IteratorConfig,
+    data::{
+        hint::{hint_name_from_gen, remove_hint},
+        storage::{storage_name_from_gen, Storage},
+    },
+    error::{KvError, Result},
+    index::IndexSnapshot,
+    iterator::Iterator,
+    merge::{remove_retired_marker, write_retired_marker},
+    Engine,
+};
+
+/// 已被合并、但仍可能被合并前创建的快照读取的`Storage`
+pub(crate) struct Retired {
+    pub(crate) storages: HashMap<u32, Storage>,
+    /// 合并前创建的快照共享的引用，仅剩此处持有时即可删除
+    pin: Arc<()>,
+}
+
+/// 阻止合并删除创建时已存在的`Storage`，释放时删除不再被引用的已合并文件
+pub(crate) struct StoragePin<'a> {
+    pub(crate) engine: &'a Engine,
+    pin: Option<Arc<()>>,
+}
+
+impl Clone for StoragePin<'_> {
+    fn clone(&self) -> Self {
+        Self {
+            engine: self.engine,
+            pin: self.pin.clone(),
+        }
+    }
+}
+
+impl Drop for StoragePin<'_> {
+    fn drop(&mut self) {
+        self.pin.take();
+        if let Err(e) = self.engine.release_retired() {
+            tracing::warn!("{}", e);
+        }
+    }
+}
+
+/// 数据库在某一时刻的只读快照，此后的写入及合并对快照不可见
+pub struct Snapshot<'a> {
+    pin: StoragePin<'a>,
+    index: Box<dyn IndexSnapshot>,
+}
+
+impl Snapshot<'_> {
+    /// 根据 key 获取快照创建时对应的数据
+    pub fn get<B: Into<Vec<u8>>>(&self, key: B) -> Result<Bytes> {
+        let key = key.into();
+        if key.is_empty() {
+            return Err(KvError::InvalidKey);
+        }
+
+        let Some(pos) = self.index.get(&key)?.filter(|pos| !pos.is_expired()) else {
+            return Err(KvError::InvalidKey);
+        };
+        self.pin.engine.read_value_from_pos(&pos)
+    }
+
+    /// 快照中 key 是否存在且未过期，只读取索引
+    pub(crate) fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.index.get(key)?.is_some_and(|pos| !pos.is_expired()))
+    }
+
+    /// 获取快照上的迭代器
+    pub fn iter(&self, config: IteratorConfig) -> Result<Iterator<'_>> {
+        let limit = config.limit;
+        let index_iter = self.index.iterator(config)?;
+        Ok(Iterator::new(self.pin.clone(), index_iter, limit))
+    }
+}
+
+impl Engine {
+    /// 创建当前数据的一致快照
+    pub fn snapshot(&self) -> Result<Snapshot<'_>> {
+        // 持有活跃文件的读锁，避免快照只包含批量写入的部分数据
+        self.snapshot_with(&self.active_storage.read())
+    }
+
+    /// 在调用方已持有的活跃文件锁下获取快照
+    pub(crate) fn snapshot_with(&self, _active_storage: &Storage) -> Result<Snapshot<'_>> {
+        // 先于索引快照持有引用，保证快照可能读取的`Storage`不会被删除
+        let pin = self.pin_storages();
+        Ok(Snapshot {
+            index: self.index.snapshot()?,
+            pin,
+        })
+    }
+
+    pub(crate) fn pin_storages(&self) -> StoragePin<'_> {
+        StoragePin {
+            engine: self,
+            pin: Some(self.snapshot_pin.lock().clone()),
+        }
+    }
+
+    /// 移除已合并的`Storage`，仍被快照引用时延迟至快照释放后删除
+    ///
+    /// 调用方需保证索引已不再指向这些`Storage`，且`gens`包含全部更早的`Storage`
+    pub(crate) fn retire_storages(&self, gens: &[u32]) -> Result<()> {
+        let Some(max_gen) = gens.iter().max() else {
+            return Ok(());
+        };
+        {
+            let mut older_storages = self.older_storages.write();
+            let mut retired = self.retired_storages.write();
+            // 删除完成前退出时，下次启动根据标记删除遗留的文件
+            write_retired_marker(&self.config.dir_path, max_gen + 1)?;
+            let storages = gens
+                .iter()
+                .filter_map(|gen| older_storages.remove_entry(gen))
+                .collect();
+            // 此后创建的快照持有新的引用，不会读取被移除的`Storage`
+            let pin = mem::replace(&mut *self.snapshot_pin.lock(), Arc::new(()));
+            retired.push(Retired { storages, pin });
+        }
+        self.release_retired()
+    }
+
+    /// 删除不再被任何快照引用的已合并`Storage`及其hint文件
+    ///
+    /// 进程退出前未能删除的文件将在下次启动时根据`retire_storages`写入的标记删除
+    pub(crate) fn release_retired(&self) -> Result<()> {
+        // 持有锁直至删除完成，避免其他调用在文件删除前移除标记
+        let mut retired = self.retired_storages.write();
+        let (released, kept) = mem::take(&mut *retired)
+            .into_iter()
+            .partition::<Vec<_>, _>(|r| Arc::strong_count(&r.pin) == 1);
+        *retired = kept;
+        if released.is_empty() {
+            return Ok(());
+        }
+
+        let dir_path = &self.config.dir_path;
+        for gen in released.into_iter().flat_map(|r| r.storages.into_keys()) {
+            std::fs::remove_file(dir_path.join(storage_name_from_gen(gen)))?;
+            remove_hint(&dir_path.join(hint_name_from_gen(gen)))?;
+        }
+        if retired.is_empty() {
+            remove_retired_marker(dir_path)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+//! 各模块测试共用的辅助函数
+
+use std::fs;
+
+use tempfile::TempDir;
+
+use crate::{
+    config::Config,
+    data::{hint::is_hint_file, storage::is_storage_file},
+    index::IndexType,
+    Engine,
+};
+
+/// 全部索引类型
+pub(crate) const INDEX_TYPES: [IndexType; 5] = [
+    IndexType::BTree,
+    IndexType::ART,
+    IndexType::SkipList,
+    IndexType::BPlusTree,
+    IndexType::HashSharded,
+];
+
+/// 以`dir`为数据目录的默认配置
+pub(crate) fn config(dir: &TempDir) -> Config {
+    Config {
+        dir_path: dir.path().to_path_buf(),
+        ..Default::default()
+    }
+}
+
+/// 以默认配置打开`dir`
+pub(crate) fn open(dir: &TempDir) -> Engine {
+    Engine::new(config(dir)).unwrap()
+}
+
+pub(crate) fn key(i: usize) -> Vec<u8> {
+    format!("key-{:04}", i).into_bytes()
+}
+
+/// 数据目录中全部`Storage`文件的大小
+pub(crate) fn storages_size(dir: &TempDir) -> u64 {
+    fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| is_storage_file(path).is_ok())
+        .map(|path| fs::metadata(path).unwrap().len())
+        .sum()
+}
+
+/// 删除数据目录中的全部hint文件，重启时从`Storage`重新构建索引
+pub(crate) fn remove_hints(dir: &TempDir) {
+    for entry in fs::read_dir(dir.path()).unwrap() {
+        let path = entry.unwrap().path();
+        if is_hint_file(&path).is_ok() {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    sync::{Arc, Weak},
+};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::{
+    data::record::{Record, RecordType},
+    error::{KvError, Result},
+    snapshot::Snapshot,
+    Engine,
+};
+
+/// 事务开始后被其他写入修改的 key
+#[derive(Default)]
+pub(crate) struct WriteSet {
+    keys: Mutex<HashSet<Vec<u8>>>,
+}
+
+/// 进行中的事务，写入时需为每个事务记录被修改的 key 以检测冲突
+#[derive(Default)]
+pub(crate) struct ActiveTransactions {
+    sets: Mutex<Vec<Weak<WriteSet>>>,
+}
+
+impl ActiveTransactions {
+    /// 注册新的事务，调用方需持有活跃文件的锁，保证此时没有进行中的写入
+    fn register(&self) -> Arc<WriteSet> {
+        let set = Arc::new(WriteSet::default());
+        let mut sets = self.sets.lock();
+        sets.retain(|s| s.strong_count() > 0);
+        sets.push(Arc::downgrade(&set));
+        set
+    }
+
+    /// 记录被修改的 key，调用方需持有活跃文件的写锁直至索引更新完成
+    pub(crate) fn record(&self, key: &[u8]) {
+        let mut sets = self.sets.lock();
+        if sets.is_empty() {
+            return;
+        }
+
+        sets.retain(|set| match set.upgrade() {
+            Some(set) => {
+                set.keys.lock().insert(key.to_vec());
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+/// 乐观事务，读取开始时的快照，提交时检测冲突
+///
+/// 事务中的写入在提交前仅对事务自身可见，提交时以批量写入的格式原子性地写入
+pub struct Transaction<'a> {
+    engine: &'a Engine,
+    snapshot: Snapshot<'a>,
+    /// 事务开始后被其他写入修改的 key
+    modified: Arc<WriteSet>,
+    /// 事务读取过的 key
+    reads: Mutex<HashSet<Vec<u8>>>,
+    pending: Mutex<HashMap<Vec<u8>, Record>>,
+}
+
+impl Engine {
+    /// 开始一个乐观事务
+    pub fn begin_transaction(&self) -> Result<Transaction<'_>> {
+        // 持有活跃文件的读锁，保证快照与冲突检测的起点一致
+        let active_storage = self.active_storage.read();
+        let snapshot = self.snapshot_with(&active_storage)?;
+        let modified = self.transactions.register();
+        Ok(Transaction {
+            engine: self,
+            snapshot,
+            modified,
+            reads: Mutex::new(HashSet::new()),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+impl Transaction<'_> {
+    /// 根据 key 获取对应的数据，可以读取到事务自身尚未提交的写入
+    pub fn get<B: Into<Vec<u8>>>(&self, key: B) -> Result<Bytes> {
+        let key = key.into();
+        if key.is_empty() {
+            return Err(KvError::InvalidKey);
+        }
+
+        if let Some(record) = self.pending.lock().get(&key) {
+            return match record.record_type {
+                RecordType::Normal => Ok(record.value.clone().into()),
+                _ => Err(KvError::InvalidKey),
+            };
+        }
+
+        let value = self.snapshot.get(key.as_slice());
+        self.reads.lock().insert(key);
+        value
+    }
+
+    /// 暂存 key，value 数据，其中 key 不为空
+    pub fn set<B: Into<Vec<u8>>>(&self, key: B, value: B) -> Result<()> {
+        let key = key.into();
+        if key.is_empty() {
+            return Err(KvError::InvalidKey);
+        }
+
+        let record = Record::new_set(key.clone(), value.into());
+        self.pending.lock().insert(key, record);
+        Ok(())
+    }
+
+    /// 暂存对 key 的删除操作
+    pub fn delete<B: Into<Vec<u8>>>(&self, key: B) -> Result<()> {
+        let key = key.into();
+        if key.is_empty() {
+            return Err(KvError::InvalidKey);
+        }
+
+        let record = Record::new_remove(key.clone());
+        self.pending.lock().insert(key, record);
+        Ok(())
+    }
+
+    /// 提交事务，读取或写入的 key 在事务开始后被其他写入修改时返回冲突错误
+    pub fn commit(self) -> Result<()> {
+        let mut pending = self.pending.into_inner();
+        let reads = self.reads.into_inner();
+
+        // 持有活跃文件的写锁，检测冲突后其他写入无法再修改这些 key
+        let mut active_storage = self.engine.active_storage.write();
+        {
+            let modified = self.modified.keys.lock();
+            if reads
+                .iter()
+                .chain(pending.keys())
+                .any(|key| modified.contains(key))
+            {
+                return Err(KvError::TransactionConflict);
+            }
+        }
+
+        // 删除事务开始时不存在的 key 无需写入
+        for (key, record) in mem::take(&mut pending) {
+            if matches!(record.record_type, RecordType::Normal)
+                || self.snapshot.contains_key(&key)?
+            {
+                pending.insert(key, record);
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+        self.engine.write_batch(
+            &mut active_storage,
+            &mut pending,
+            self.engine.config.sync_write,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::test_util::open;
+
+    #[test]
+    fn reads_own_writes_and_hides_them_until_commit() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine.set("a", "1").unwrap();
+
+        let txn = engine.begin_transaction().unwrap();
+        txn.set("a", "2").unwrap();
+        txn.set("b", "3").unwrap();
+        txn.delete("a").unwrap();
+        assert!(matches!(txn.get("a"), Err(KvError::InvalidKey)));
+        assert_eq!(txn.get("b").unwrap(), "3");
+        assert_eq!(engine.get("a").unwrap(), "1");
+        assert!(matches!(engine.get("b"), Err(KvError::InvalidKey)));
+
+        txn.commit().unwrap();
+        assert!(matches!(engine.get("a"), Err(KvError::InvalidKey)));
+        assert_eq!(engine.get("b").unwrap(), "3");
+    }
+
+    #[test]
+    fn reads_the_snapshot_at_begin() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine.set("a", "1").unwrap();
+
+        let txn = engine.begin_transaction().unwrap();
+        engine.set("a", "2").unwrap();
+        engine.set("b", "2").unwrap();
+        assert_eq!(txn.get("a").unwrap(), "1");
+        assert!(matches!(txn.get("b"), Err(KvError::InvalidKey)));
+    }
+
+    #[test]
+    fn modified_read_key_conflicts() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine.set("a", "1").unwrap();
+
+        let txn = engine.begin_transaction().unwrap();
+        txn.get("a").unwrap();
+        txn.set("b", "1").unwrap();
+        engine.delete("a").unwrap();
+        assert!(matches!(txn.commit(), Err(KvError::TransactionConflict)));
+        // 冲突的事务没有任何写入
+        assert!(matches!(engine.get("b"), Err(KvError::InvalidKey)));
+
+        // 读取不存在的 key 同样参与冲突检测
+        let txn = engine.begin_transaction().unwrap();
+        assert!(txn.get("c").is_err());
+        engine.set("c", "1").unwrap();
+        assert!(matches!(txn.commit(), Err(KvError::TransactionConflict)));
+    }
+
+    #[test]
+    fn modified_written_key_conflicts() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+
+        let first = engine.begin_transaction().unwrap();
+        let second = engine.begin_transaction().unwrap();
+        first.set("a", "1").unwrap();
+        second.set("a", "2").unwrap();
+        second.commit().unwrap();
+        assert!(matches!(first.commit(), Err(KvError::TransactionConflict)));
+        assert_eq!(engine.get("a").unwrap(), "2");
+    }
+
+    #[test]
+    fn unrelated_writes_do_not_conflict() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine.set("a", "1").unwrap();
+
+        let txn = engine.begin_transaction().unwrap();
+        txn.get("a").unwrap();
+        txn.set("b", "1").unwrap();
+        engine.set("c", "1").unwrap();
+        // 事务开始后对其他 key 的修改不会冲突
+        txn.commit().unwrap();
+        assert_eq!(engine.get("b").unwrap(), "1");
+    }
+
+    #[test]
+    fn concurrent_increments_are_not_lost() {
+        const THREADS: usize = 4;
+        const INCREMENTS: usize = 50;
+
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine.set("counter", "0").unwrap();
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..INCREMENTS {
+                        // 冲突时重试，直至提交成功
+                        loop {
+                            let txn = engine.begin_transaction().unwrap();
+                            let value = txn.get("counter").unwrap();
+                            let n = std::str::from_utf8(&value)
+                                .unwrap()
+                                .parse::<usize>()
+                                .unwrap();
+                            txn.set("counter".to_string(), (n + 1).to_string()).unwrap();
+                            match txn.commit() {
+                                Ok(()) => break,
+                                Err(KvError::TransactionConflict) => continue,
+                                Err(e) => panic!("{}", e),
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(
+            engine.get("counter").unwrap(),
+            (THREADS * INCREMENTS).to_string()
+        );
+    }
+}
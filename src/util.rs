@@ -0,0 +1,30 @@
+use std::{
+    fs::{self, File},
+    path::Path,
+};
+
+use crate::error::Result;
+
+/// 将`data`写入`dir_path`下名为`name`的文件
+///
+/// 先写入临时文件并落盘再重命名，保证文件总是完整的，重命名后同步目录使其持久化
+pub(crate) fn write_file_atomically(dir_path: &Path, name: &str, data: &[u8]) -> Result<()> {
+    let tmp_path = dir_path.join(format!("{}.tmp", name));
+    fs::write(&tmp_path, data)?;
+    File::open(&tmp_path)?.sync_all()?;
+    fs::rename(&tmp_path, dir_path.join(name))?;
+    sync_dir(dir_path)
+}
+
+/// 同步目录，使其中文件的创建、重命名及删除持久化
+#[cfg(unix)]
+pub(crate) fn sync_dir(dir_path: &Path) -> Result<()> {
+    File::open(dir_path)?.sync_all()?;
+    Ok(())
+}
+
+/// Windows 无法打开目录同步，重命名的持久化由文件系统保证
+#[cfg(not(unix))]
+pub(crate) fn sync_dir(_dir_path: &Path) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,368 @@
+//! 在回环地址上启动`tinykv-server`，通过 TCP 连接以 RESP 协议验证命令
+
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use tempfile::TempDir;
+
+/// 运行中的服务器进程，释放时结束进程
+struct Server {
+    child: Child,
+    addr: String,
+    dir: Option<TempDir>,
+}
+
+impl Server {
+    fn start() -> Self {
+        Self::start_in(TempDir::new().unwrap())
+    }
+
+    fn start_in(dir: TempDir) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_tinykv-server"))
+            .args(["--addr", "127.0.0.1:0", "--dir"])
+            .arg(dir.path())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // 从启动日志中读取实际监听的地址
+        let mut stderr = BufReader::new(child.stderr.take().unwrap());
+        let mut line = String::new();
+        stderr.read_line(&mut line).unwrap();
+        let addr = line
+            .trim()
+            .strip_prefix("tinykv-server listening on ")
+            .unwrap_or_else(|| panic!("unexpected output: {}", line))
+            .to_string();
+        // 持续读取日志，避免管道写满后阻塞服务器
+        thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+
+        Self {
+            child,
+            addr,
+            dir: Some(dir),
+        }
+    }
+
+    /// 发送 SIGTERM 并等待服务器退出，返回其数据目录
+    #[cfg(unix)]
+    fn terminate(mut self) -> TempDir {
+        let status = Command::new("kill")
+            .args(["-TERM", &self.child.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert!(self.child.wait().unwrap().success());
+        self.dir.take().unwrap()
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(&self.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn bulk(s: &str) -> Self {
+        Value::Bulk(Some(s.as_bytes().to_vec()))
+    }
+
+    fn into_strings(self) -> Vec<String> {
+        let Value::Array(items) = self else {
+            panic!("expected array, got {:?}", self);
+        };
+        items
+            .into_iter()
+            .map(|item| match item {
+                Value::Bulk(Some(data)) => String::from_utf8(data).unwrap(),
+                other => panic!("expected bulk string, got {:?}", other),
+            })
+            .collect()
+    }
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    /// 以 RESP 数组发送命令并读取回复
+    fn cmd(&mut self, args: &[&str]) -> Value {
+        let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            buf.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+        }
+        self.send(&buf);
+        self.read_reply()
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        self.writer.write_all(data).unwrap();
+    }
+
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        assert!(line.ends_with("\r\n"), "unterminated reply: {:?}", line);
+        line.truncate(line.len() - 2);
+        line
+    }
+
+    fn read_reply(&mut self) -> Value {
+        let line = self.read_line();
+        let (kind, rest) = line.split_at(1);
+        match kind {
+            "+" => Value::Simple(rest.to_string()),
+            "-" => Value::Error(rest.to_string()),
+            ":" => Value::Integer(rest.parse().unwrap()),
+            "$" if rest == "-1" => Value::Bulk(None),
+            "$" => {
+                let mut data = vec![0; rest.parse::<usize>().unwrap() + 2];
+                self.reader.read_exact(&mut data).unwrap();
+                data.truncate(data.len() - 2);
+                Value::Bulk(Some(data))
+            }
+            "*" => Value::Array(
+                (0..rest.parse::<usize>().unwrap())
+                    .map(|_| self.read_reply())
+                    .collect(),
+            ),
+            _ => panic!("unexpected reply: {:?}", line),
+        }
+    }
+
+    /// 连接是否已被服务器关闭
+    fn is_closed(&mut self) -> bool {
+        let mut buf = [0; 1];
+        matches!(self.reader.read(&mut buf), Ok(0))
+    }
+}
+
+fn ok() -> Value {
+    Value::Simple("OK".to_string())
+}
+
+fn is_error(value: &Value, prefix: &str) -> bool {
+    matches!(value, Value::Error(e) if e.starts_with(prefix))
+}
+
+#[test]
+fn get_set_del_exists() {
+    let server = Server::start();
+    let mut client = server.connect();
+
+    assert_eq!(client.cmd(&["PING"]), Value::Simple("PONG".to_string()));
+    assert_eq!(client.cmd(&["GET", "a"]), Value::Bulk(None));
+    assert_eq!(client.cmd(&["SET", "a", "1"]), ok());
+    assert_eq!(client.cmd(&["SET", "b", "hello world"]), ok());
+    assert_eq!(client.cmd(&["GET", "a"]), Value::bulk("1"));
+    assert_eq!(client.cmd(&["GET", "b"]), Value::bulk("hello world"));
+
+    // 重复的 key 被重复计数
+    assert_eq!(
+        client.cmd(&["EXISTS", "a", "b", "a", "c"]),
+        Value::Integer(3)
+    );
+    assert_eq!(client.cmd(&["DEL", "a", "c"]), Value::Integer(1));
+    assert_eq!(client.cmd(&["GET", "a"]), Value::Bulk(None));
+    assert_eq!(client.cmd(&["EXISTS", "a"]), Value::Integer(0));
+    assert_eq!(client.cmd(&["DBSIZE"]), Value::Integer(1));
+
+    assert!(is_error(
+        &client.cmd(&["GET"]),
+        "ERR wrong number of arguments"
+    ));
+    assert!(is_error(&client.cmd(&["NOSUCH"]), "ERR unknown command"));
+    assert_eq!(client.cmd(&["QUIT"]), ok());
+    assert!(client.is_closed());
+}
+
+#[test]
+fn set_with_expiry() {
+    let server = Server::start();
+    let mut client = server.connect();
+
+    assert_eq!(client.cmd(&["SET", "px", "1", "PX", "100"]), ok());
+    assert_eq!(client.cmd(&["SET", "ex", "1", "ex", "3600"]), ok());
+    assert_eq!(client.cmd(&["GET", "px"]), Value::bulk("1"));
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(client.cmd(&["GET", "px"]), Value::Bulk(None));
+    assert_eq!(client.cmd(&["EXISTS", "px"]), Value::Integer(0));
+    assert_eq!(client.cmd(&["GET", "ex"]), Value::bulk("1"));
+
+    for args in [
+        &["SET", "k", "v", "EX", "0"][..],
+        &["SET", "k", "v", "PX", "-1"],
+        &["SET", "k", "v", "EX", "abc"],
+    ] {
+        assert!(is_error(&client.cmd(args), "ERR invalid expire time"));
+    }
+    assert!(is_error(
+        &client.cmd(&["SET", "k", "v", "KEEPTTL", "1"]),
+        "ERR syntax error"
+    ));
+    assert_eq!(client.cmd(&["GET", "k"]), Value::Bulk(None));
+}
+
+#[test]
+fn scan_pages_and_keys_glob() {
+    let server = Server::start();
+    let mut client = server.connect();
+
+    let mut users = HashSet::new();
+    for i in 0..25 {
+        let key = format!("user:{:02}", i);
+        assert_eq!(client.cmd(&["SET", &key, "v"]), ok());
+        users.insert(key);
+    }
+    for key in ["order:1", "order:2", "u"] {
+        assert_eq!(client.cmd(&["SET", key, "v"]), ok());
+    }
+
+    // 以游标分页，每个 key 恰好返回一次
+    let mut cursor = "0".to_string();
+    let mut scanned = Vec::new();
+    let mut pages = 0;
+    loop {
+        let reply = client.cmd(&["SCAN", &cursor, "MATCH", "user:*", "COUNT", "10"]);
+        let Value::Array(mut parts) = reply else {
+            panic!("unexpected reply: {:?}", reply);
+        };
+        let keys = parts.pop().unwrap().into_strings();
+        let Some(Value::Bulk(Some(next))) = parts.pop() else {
+            panic!("missing cursor");
+        };
+        scanned.extend(keys);
+        pages += 1;
+        cursor = String::from_utf8(next).unwrap();
+        if cursor == "0" {
+            break;
+        }
+    }
+    assert_eq!(pages, 3);
+    assert_eq!(scanned.len(), users.len());
+    assert_eq!(scanned.into_iter().collect::<HashSet<_>>(), users);
+
+    let mut keys = client.cmd(&["KEYS", "order:*"]).into_strings();
+    keys.sort();
+    assert_eq!(keys, ["order:1", "order:2"]);
+    let mut keys = client.cmd(&["KEYS", "user:0[1-3]"]).into_strings();
+    keys.sort();
+    assert_eq!(keys, ["user:01", "user:02", "user:03"]);
+    assert_eq!(client.cmd(&["KEYS", "*"]).into_strings().len(), 28);
+    assert_eq!(client.cmd(&["KEYS", "?"]).into_strings(), ["u"]);
+
+    assert!(is_error(
+        &client.cmd(&["SCAN", "12345"]),
+        "ERR invalid cursor"
+    ));
+    assert!(is_error(
+        &client.cmd(&["SCAN", "0", "COUNT", "0"]),
+        "ERR syntax error"
+    ));
+}
+
+#[test]
+fn concurrent_connections() {
+    const CLIENTS: usize = 8;
+    const KEYS: usize = 50;
+
+    let server = Server::start();
+    thread::scope(|s| {
+        for c in 0..CLIENTS {
+            let mut client = server.connect();
+            s.spawn(move || {
+                for i in 0..KEYS {
+                    let key = format!("client-{}-{}", c, i);
+                    assert_eq!(client.cmd(&["SET", &key, &i.to_string()]), ok());
+                    assert_eq!(client.cmd(&["GET", &key]), Value::bulk(&i.to_string()));
+                }
+                for i in (0..KEYS).step_by(2) {
+                    let key = format!("client-{}-{}", c, i);
+                    assert_eq!(client.cmd(&["DEL", &key]), Value::Integer(1));
+                }
+            });
+        }
+    });
+
+    let mut client = server.connect();
+    assert_eq!(
+        client.cmd(&["DBSIZE"]),
+        Value::Integer((CLIENTS * KEYS / 2) as i64)
+    );
+}
+
+#[test]
+fn malformed_requests_get_an_error() {
+    let server = Server::start();
+
+    for request in [
+        &b"*1\r\n:5\r\n"[..],
+        b"*x\r\n",
+        b"*1\r\n$abc\r\n",
+        b"*1\r\n$3\r\nabcdef\r\n",
+        b"*1\r\n$99999999999\r\n",
+    ] {
+        let mut client = server.connect();
+        client.send(request);
+        let reply = client.read_reply();
+        assert!(is_error(&reply, "ERR Protocol error"), "{:?}", reply);
+        // 协议错误后连接被关闭
+        assert!(client.is_closed());
+    }
+
+    // 服务器没有因此退出，新的连接仍可正常使用
+    let mut client = server.connect();
+    client.send(b"SET inline value\r\n");
+    assert_eq!(client.read_reply(), ok());
+    assert_eq!(client.cmd(&["GET", "inline"]), Value::bulk("value"));
+}
+
+#[cfg(unix)]
+#[test]
+fn sigterm_closes_engine() {
+    let server = Server::start();
+    let mut client = server.connect();
+    assert_eq!(client.cmd(&["SET", "a", "1"]), ok());
+    assert_eq!(client.cmd(&["SET", "b", "2"]), ok());
+    assert_eq!(client.cmd(&["DBSIZE"]), Value::Integer(2));
+
+    // 空闲的连接不会阻止服务器退出，退出前写入活跃文件的hint文件
+    let dir = server.terminate();
+    assert!(client.is_closed());
+    assert!(dir.path().join("000000000.hint").is_file());
+
+    let server = Server::start_in(dir);
+    let mut client = server.connect();
+    assert_eq!(client.cmd(&["GET", "a"]), Value::bulk("1"));
+    assert_eq!(client.cmd(&["DBSIZE"]), Value::Integer(2));
+}